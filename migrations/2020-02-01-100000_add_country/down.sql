@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE states DROP COLUMN source;
+
+DELETE FROM addresses WHERE country <> 'NL';
+ALTER TABLE addresses DROP CONSTRAINT u_postcode_number_country;
+ALTER TABLE addresses ADD CONSTRAINT u_postcode_number UNIQUE (postcode, number);
+ALTER TABLE addresses DROP COLUMN country;
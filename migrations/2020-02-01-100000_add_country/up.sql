@@ -0,0 +1,13 @@
+-- Existing data was imported from the Dutch dataset only
+ALTER TABLE addresses ADD COLUMN country TEXT NOT NULL DEFAULT 'NL';
+ALTER TABLE addresses ALTER COLUMN country DROP DEFAULT;
+
+-- Postcodes are only unique within a country. The country is kept
+-- last so that the index can still be used when searching by
+-- postcode (and number) only.
+ALTER TABLE addresses DROP CONSTRAINT u_postcode_number;
+ALTER TABLE addresses ADD CONSTRAINT u_postcode_number_country UNIQUE (postcode, number, country);
+
+-- Each OpenAddresses source is tracked separately
+ALTER TABLE states ADD COLUMN source TEXT NOT NULL DEFAULT 'nl/countrywide';
+ALTER TABLE states ALTER COLUMN source DROP DEFAULT;
@@ -0,0 +1,2 @@
+-- Used to narrow down reverse geocoding queries to a bounding box
+CREATE INDEX addresses_lat_lon ON addresses (lat, lon);
@@ -0,0 +1,7 @@
+-- Full text index used by autocomplete, every term of the input is
+-- matched as a prefix against street, number, city and postcode.
+-- The expression must be kept in sync with the autocomplete query
+-- in data::repo::addresses for the index to be used.
+CREATE INDEX addresses_search ON addresses USING GIN (
+    to_tsvector('simple', street || ' ' || number || ' ' || city || ' ' || postcode)
+);
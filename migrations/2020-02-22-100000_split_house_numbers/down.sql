@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX addresses_postcode_house_number;
+ALTER TABLE addresses DROP COLUMN addition;
+ALTER TABLE addresses DROP COLUMN letter;
+ALTER TABLE addresses DROP COLUMN house_number;
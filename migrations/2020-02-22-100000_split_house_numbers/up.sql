@@ -0,0 +1,18 @@
+-- Dutch house numbers are made of a number, an optional letter (huisletter)
+-- directly following it and an optional addition (toevoeging),
+-- e.g. "12A-1", "12 bis" or "12-II". `number` keeps the original value.
+ALTER TABLE addresses ADD COLUMN house_number INTEGER;
+ALTER TABLE addresses ADD COLUMN letter TEXT;
+ALTER TABLE addresses ADD COLUMN addition TEXT;
+
+-- Same rules as data::house_number::parse_house_number
+UPDATE addresses SET
+    house_number = substring(number from '^\s*([0-9]{1,9})')::INTEGER,
+    letter = upper(substring(number from '^\s*[0-9]{1,9}([A-Za-z])(?![A-Za-z])')),
+    addition = NULLIF(
+        upper(btrim(regexp_replace(number, '^\s*[0-9]{1,9}([A-Za-z](?![A-Za-z]))?', ''), E' \t-/')),
+        ''
+    )
+WHERE number ~ '^\s*[0-9]{1,9}([^0-9]|$)';
+
+CREATE INDEX addresses_postcode_house_number ON addresses (postcode, house_number);
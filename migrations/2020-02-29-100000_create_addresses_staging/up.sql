@@ -0,0 +1,8 @@
+-- Imports are loaded in this table first, and only copied to `addresses`
+-- once complete, in a single transaction. It is emptied before and after
+-- each import, so it doesn't need to survive a crash.
+CREATE UNLOGGED TABLE addresses_staging (
+    LIKE addresses INCLUDING DEFAULTS,
+    PRIMARY KEY (id),
+    CONSTRAINT u_staging_postcode_number_country UNIQUE (postcode, number, country)
+);
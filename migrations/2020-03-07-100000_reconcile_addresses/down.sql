@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE states DROP COLUMN removed_count;
+ALTER TABLE states DROP COLUMN updated_count;
+ALTER TABLE states DROP COLUMN inserted_count;
+ALTER TABLE addresses_staging DROP COLUMN source;
+DROP INDEX addresses_source;
+ALTER TABLE addresses DROP COLUMN source;
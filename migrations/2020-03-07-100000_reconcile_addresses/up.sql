@@ -0,0 +1,24 @@
+-- Addresses belong to the source they were last imported from, so that
+-- addresses removed from a source can be removed from the table as well.
+-- Existing addresses are attributed to the latest source of their country,
+-- the others are left without source and adopted by the next import
+-- containing them.
+ALTER TABLE addresses ADD COLUMN source TEXT NOT NULL DEFAULT '';
+UPDATE addresses SET source = latest.source
+FROM (
+    SELECT DISTINCT ON (upper(split_part(source, '/', 1)))
+        upper(split_part(source, '/', 1)) AS country,
+        source
+    FROM states
+    ORDER BY upper(split_part(source, '/', 1)), processed_at DESC
+) AS latest
+WHERE addresses.country = latest.country;
+ALTER TABLE addresses ALTER COLUMN source DROP DEFAULT;
+CREATE INDEX addresses_source ON addresses (source);
+
+ALTER TABLE addresses_staging ADD COLUMN source TEXT NOT NULL;
+
+-- Changes made to the addresses table by each import
+ALTER TABLE states ADD COLUMN inserted_count BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE states ADD COLUMN updated_count BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE states ADD COLUMN removed_count BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,21 @@
+-- Differences between the addresses of a state and the previous state
+-- of the same source. Previous values are NULL for added addresses,
+-- new values are NULL for removed addresses.
+CREATE TABLE address_changes (
+    id BIGSERIAL PRIMARY KEY,
+    state_id UUID NOT NULL REFERENCES states (id) ON DELETE CASCADE,
+    change TEXT NOT NULL CHECK (change IN ('added', 'removed', 'moved', 'renamed')),
+    postcode TEXT NOT NULL,
+    number TEXT NOT NULL,
+    country TEXT NOT NULL,
+    street TEXT,
+    city TEXT,
+    lat FLOAT8,
+    lon FLOAT8,
+    previous_street TEXT,
+    previous_city TEXT,
+    previous_lat FLOAT8,
+    previous_lon FLOAT8
+);
+
+CREATE INDEX address_changes_state_id ON address_changes (state_id);
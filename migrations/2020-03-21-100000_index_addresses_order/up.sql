@@ -0,0 +1,11 @@
+-- Addresses of a postcode are listed by house number, then by number and
+-- country. The text columns are compared byte by byte so that the order
+-- doesn't depend on the database collation, and is the same in memory.
+-- The expressions must be kept in sync with get_addresses in
+-- data::repo::addresses for the index to be used.
+CREATE INDEX addresses_postcode_order ON addresses (
+    postcode,
+    coalesce(house_number, 2147483647),
+    number COLLATE "C",
+    country COLLATE "C"
+);
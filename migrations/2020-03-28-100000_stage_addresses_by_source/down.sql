@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+TRUNCATE addresses_staging;
+ALTER TABLE addresses_staging DROP CONSTRAINT u_staging_source_postcode_number_country;
+ALTER TABLE addresses_staging
+    ADD CONSTRAINT u_staging_postcode_number_country UNIQUE (postcode, number, country);
@@ -0,0 +1,5 @@
+-- Imports only stage, publish and clear the addresses of their own source,
+-- so the same address can be staged for several sources.
+ALTER TABLE addresses_staging DROP CONSTRAINT u_staging_postcode_number_country;
+ALTER TABLE addresses_staging
+    ADD CONSTRAINT u_staging_source_postcode_number_country UNIQUE (source, postcode, number, country);
@@ -0,0 +1,14 @@
+-- Number of addresses per region of each source, updated when the source is
+-- published so that reporting them doesn't scan the addresses table.
+CREATE TABLE region_counts (
+    source TEXT NOT NULL,
+    country TEXT NOT NULL,
+    region TEXT NOT NULL,
+    count BIGINT NOT NULL,
+    PRIMARY KEY (source, country, region)
+);
+
+INSERT INTO region_counts (source, country, region, count)
+SELECT source, country, region, count(*)
+FROM addresses
+GROUP BY source, country, region;
@@ -1,34 +1,474 @@
-use actix_web::{Error, HttpResponse, web};
-use log::error;
-use serde::Deserialize;
+use std::collections::HashMap;
 
-use crate::data::repo::addresses::get_addresses;
+use actix_web::{HttpResponse, web};
+use diesel::PgConnection;
+use serde::{Deserialize, Serialize};
+
+use crate::api::{HAS_MORE_HEADER, NEXT_CURSOR_HEADER};
+use crate::api::error::ApiError;
+use crate::api::request_id::RequestId;
+use crate::config::ApiConfig;
+use crate::data::house_number::parse_house_number;
+use crate::data::models::Address;
+use crate::data::repo::addresses::{
+    AddressCursor,
+    AddressPage,
+    get_addresses,
+    get_addresses_by_postcodes,
+    get_nearest_addresses,
+    HouseNumberFilter,
+    MatchMode,
+    search_addresses,
+    sort_key,
+};
+use crate::data::repo::states::has_state;
 use crate::db::Pool;
+use crate::postcode::{normalize_postcode, PostcodeError, rule_for};
+use crate::utils::ExistsExtension;
+
+const DEFAULT_NEAREST_RADIUS_METERS: f64 = 100.0;
+const MAX_NEAREST_RADIUS_METERS: f64 = 5000.0;
+const DEFAULT_NEAREST_LIMIT: i64 = 10;
+const MAX_NEAREST_LIMIT: i64 = 100;
+const MAX_BATCH_SIZE: usize = 1000;
+/// Addresses loaded at most to answer a batch
+const MAX_BATCH_ADDRESSES: i64 = 100_000;
+const MIN_AUTOCOMPLETE_LENGTH: usize = 2;
+const DEFAULT_AUTOCOMPLETE_LIMIT: i64 = 10;
+const MAX_AUTOCOMPLETE_LIMIT: i64 = 50;
 
 #[derive(Deserialize)]
 pub struct AddressRequest {
+    country: Option<String>,
+    postcode: String,
+    number: Option<String>,
+    letter: Option<String>,
+    addition: Option<String>,
+    #[serde(rename = "match")]
+    match_mode: Option<MatchMode>,
+    limit: Option<i64>,
+    cursor: Option<String>
+}
+
+/// Validated and normalized `AddressRequest`
+struct AddressQuery {
+    country: Option<String>,
     postcode: String,
-    number: Option<String>
+    filter: HouseNumberFilter,
+    limit: i64,
+    after: Option<AddressCursor>
+}
+
+impl AddressQuery {
+    fn matches(&self, address: &Address) -> bool {
+        self.country.as_ref().map_or(true, |c| &address.country == c)
+            && self.filter.matches(address)
+            && self.after.as_ref().map_or(true, |cursor| cursor.precedes(address))
+    }
+
+    /// Same as `get_addresses`, for the addresses of the postcode already
+    /// loaded in memory, sorted by `sort_key`.
+    fn page(&self, addresses: &[Address]) -> AddressPage {
+        let mut matches = addresses
+            .iter()
+            .filter(|address| self.matches(address))
+            .take(self.limit as usize + 1)
+            .cloned()
+            .collect::<Vec<Address>>();
+
+        let next_cursor = if matches.len() as i64 > self.limit {
+            matches.truncate(self.limit as usize);
+            matches.last().map(AddressCursor::from)
+        } else {
+            None
+        };
+
+        AddressPage { addresses: matches, next_cursor }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum BatchStatus {
+    Ok,
+    NotFound,
+    Invalid,
+}
+
+#[derive(Serialize)]
+struct BatchResult {
+    status: BatchStatus,
+    addresses: Vec<Address>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ApiError>
+}
+
+#[derive(Deserialize)]
+pub struct NearestRequest {
+    country: Option<String>,
+    lat: f64,
+    lon: f64,
+    radius: Option<f64>,
+    limit: Option<i64>
+}
+
+#[derive(Deserialize)]
+pub struct AutocompleteRequest {
+    country: Option<String>,
+    q: String,
+    limit: Option<i64>
 }
 
 pub async fn addresses(
+    request_id: RequestId,
     request: web::Query<AddressRequest>,
+    pool: web::Data<Pool>,
+    config: web::Data<ApiConfig>
+) -> Result<HttpResponse, ApiError> {
+    let query = address_query(request.into_inner(), config.addresses_max_limit)
+        .map_err(|err| err.request_id(&request_id))?;
+
+    let page = web::block(move || -> Result<_, ApiError> {
+        let conn = pool.get()?;
+        let page = get_addresses(
+            &conn,
+            query.country.as_deref(),
+            &query.postcode,
+            &query.filter,
+            query.limit,
+            query.after.as_ref()
+        )?;
+        // An empty result is only meaningful once data has been imported
+        if page.addresses.is_empty() && !has_state(&conn)? {
+            return Err(ApiError::data_not_ready());
+        }
+        Ok(page)
+    })
+    .await
+    .map_err(|err| ApiError::from(err).request_id(&request_id))?;
+
+    let mut response = HttpResponse::Ok();
+    response.header(HAS_MORE_HEADER, page.next_cursor.is_some().to_string());
+    if let Some(cursor) = &page.next_cursor {
+        response.header(NEXT_CURSOR_HEADER, cursor.encode());
+    }
+    Ok(response.json(page.addresses))
+}
+
+/// Looks up many addresses at once, results are returned in the same
+/// order as the queries. Invalid queries don't fail the whole batch.
+pub async fn batch_addresses(
+    request_id: RequestId,
+    requests: web::Json<Vec<AddressRequest>>,
+    pool: web::Data<Pool>,
+    config: web::Data<ApiConfig>
+) -> Result<HttpResponse, ApiError> {
+    let requests = requests.into_inner();
+    if requests.len() > MAX_BATCH_SIZE {
+        return Err(ApiError::invalid_body(
+            format!("A batch can contain at most {} queries", MAX_BATCH_SIZE)
+        ).request_id(&request_id));
+    }
+
+    let queries = requests
+        .into_iter()
+        .map(|request| address_query(request, config.addresses_max_limit))
+        .collect::<Vec<Result<AddressQuery, ApiError>>>();
+
+    let results = web::block(move || -> Result<_, ApiError> {
+        let valid_queries = queries
+            .iter()
+            .filter_map(|query| query.as_ref().ok())
+            .collect::<Vec<&AddressQuery>>();
+        let conn = pool.get()?;
+        let addresses_by_postcode = if valid_queries.is_empty() {
+            HashMap::new()
+        } else {
+            load_batch_addresses(&conn, &valid_queries)?
+        };
+        if addresses_by_postcode.is_empty() && !valid_queries.is_empty() && !has_state(&conn)? {
+            return Err(ApiError::data_not_ready());
+        }
+
+        let results = queries
+            .into_iter()
+            .map(|query| match query {
+                Ok(query) => {
+                    let page = match addresses_by_postcode.get(&query.postcode) {
+                        Some(postcode_addresses) => query.page(postcode_addresses),
+                        None => AddressPage { addresses: vec![], next_cursor: None },
+                    };
+                    let status = if page.addresses.is_empty() {
+                        BatchStatus::NotFound
+                    } else {
+                        BatchStatus::Ok
+                    };
+                    BatchResult {
+                        status,
+                        addresses: page.addresses,
+                        next_cursor: page.next_cursor.map(|cursor| cursor.encode()),
+                        error: None
+                    }
+                },
+                Err(err) => BatchResult {
+                    status: BatchStatus::Invalid,
+                    addresses: vec![],
+                    next_cursor: None,
+                    error: Some(err)
+                },
+            })
+            .collect::<Vec<BatchResult>>();
+        Ok(results)
+    })
+    .await
+    .map_err(|err| ApiError::from(err).request_id(&request_id))?;
+
+    Ok(HttpResponse::Ok().json(results))
+}
+
+/// Addresses of the postcodes of the batch queries, in a single query.
+/// They are grouped by postcode and sorted like `get_addresses` does,
+/// so that cursors can be used with either endpoint.
+fn load_batch_addresses(
+    conn: &PgConnection,
+    queries: &[&AddressQuery]
+) -> Result<HashMap<String, Vec<Address>>, ApiError> {
+    // Postcodes only looked up with a house number are only loaded
+    // for these numbers, the others are loaded whole.
+    let mut postcodes = vec![];
+    let mut numbered_postcodes = vec![];
+    let mut house_numbers = vec![];
+    for query in queries {
+        match query.filter.number {
+            Some(number) => {
+                numbered_postcodes.push(query.postcode.clone());
+                house_numbers.push(number);
+            },
+            None => postcodes.push(query.postcode.clone()),
+        }
+    }
+    postcodes.sort();
+    postcodes.dedup();
+    numbered_postcodes.retain(|postcode| postcodes.binary_search(postcode).is_err());
+    numbered_postcodes.sort();
+    numbered_postcodes.dedup();
+    house_numbers.sort();
+    house_numbers.dedup();
+
+    let addresses = get_addresses_by_postcodes(
+        conn,
+        &postcodes,
+        &numbered_postcodes,
+        &house_numbers,
+        MAX_BATCH_ADDRESSES + 1
+    )?;
+    if addresses.len() as i64 > MAX_BATCH_ADDRESSES {
+        return Err(ApiError::invalid_body(format!(
+            "The queries match more than {} addresses, split the batch or add house numbers",
+            MAX_BATCH_ADDRESSES
+        )));
+    }
+
+    let mut addresses_by_postcode: HashMap<String, Vec<Address>> = HashMap::new();
+    for address in addresses {
+        addresses_by_postcode
+            .entry(address.postcode.clone())
+            .or_default()
+            .push(address);
+    }
+    for postcode_addresses in addresses_by_postcode.values_mut() {
+        postcode_addresses.sort_by(|a, b| sort_key(a).cmp(&sort_key(b)));
+    }
+
+    Ok(addresses_by_postcode)
+}
+
+pub async fn nearest_addresses(
+    request_id: RequestId,
+    request: web::Query<NearestRequest>,
     pool: web::Data<Pool>
-) -> Result<HttpResponse, Error> {
-    let result = web::block(move || {
-        get_addresses(
-            &pool,
-            &request.postcode,
-            request.number.as_ref().map(|n| n.as_str())
-        )
+) -> Result<HttpResponse, ApiError> {
+    let NearestRequest { country, lat, lon, radius, limit } = request.into_inner();
+    let radius = radius.unwrap_or(DEFAULT_NEAREST_RADIUS_METERS);
+    let limit = limit.unwrap_or(DEFAULT_NEAREST_LIMIT);
+    let country = country.map(|c| c.trim().to_uppercase());
+
+    let validation = if !(-90.0..=90.0).contains(&lat) {
+        Err(ApiError::invalid_query("lat", "lat must be between -90 and 90"))
+    } else if !(-180.0..=180.0).contains(&lon) {
+        Err(ApiError::invalid_query("lon", "lon must be between -180 and 180"))
+    } else if !(radius > 0.0 && radius <= MAX_NEAREST_RADIUS_METERS) {
+        Err(ApiError::invalid_query(
+            "radius",
+            format!("radius must be between 0 and {} meters", MAX_NEAREST_RADIUS_METERS)
+        ))
+    } else if !(1..=MAX_NEAREST_LIMIT).contains(&limit) {
+        Err(ApiError::invalid_query(
+            "limit",
+            format!("limit must be between 1 and {}", MAX_NEAREST_LIMIT)
+        ))
+    } else if country.as_deref().exists(|c| rule_for(c).is_none()) {
+        Err(PostcodeError::UnsupportedCountry(country.clone().unwrap_or_default()).into())
+    } else {
+        Ok(())
+    };
+    validation.map_err(|err: ApiError| err.request_id(&request_id))?;
+
+    let addresses = web::block(move || -> Result<_, ApiError> {
+        let conn = pool.get()?;
+        let addresses = get_nearest_addresses(
+            &conn,
+            country.as_deref(),
+            lat,
+            lon,
+            radius,
+            limit
+        )?;
+        if addresses.is_empty() && !has_state(&conn)? {
+            return Err(ApiError::data_not_ready());
+        }
+        Ok(addresses)
     })
-    .await;
+    .await
+    .map_err(|err| ApiError::from(err).request_id(&request_id))?;
+
+    Ok(HttpResponse::Ok().json(addresses))
+}
+
+pub async fn autocomplete_addresses(
+    request_id: RequestId,
+    request: web::Query<AutocompleteRequest>,
+    pool: web::Data<Pool>
+) -> Result<HttpResponse, ApiError> {
+    let AutocompleteRequest { country, q, limit } = request.into_inner();
+    let limit = limit.unwrap_or(DEFAULT_AUTOCOMPLETE_LIMIT);
+    let country = country.map(|c| c.trim().to_uppercase());
+    let terms = search_terms(&q);
 
-    match result {
-        Ok(addresses) => { Ok(HttpResponse::Ok().json(addresses)) },
-        Err(err) => {
-            error!("Error while retrieving addresses: {}", err);
-            Ok(HttpResponse::InternalServerError().finish())
-        },
+    let validation = if terms.iter().map(String::len).sum::<usize>() < MIN_AUTOCOMPLETE_LENGTH {
+        Err(ApiError::invalid_query(
+            "q",
+            format!("q must contain at least {} letters or digits", MIN_AUTOCOMPLETE_LENGTH)
+        ))
+    } else if !(1..=MAX_AUTOCOMPLETE_LIMIT).contains(&limit) {
+        Err(ApiError::invalid_query(
+            "limit",
+            format!("limit must be between 1 and {}", MAX_AUTOCOMPLETE_LIMIT)
+        ))
+    } else if country.as_deref().exists(|c| rule_for(c).is_none()) {
+        Err(PostcodeError::UnsupportedCountry(country.clone().unwrap_or_default()).into())
+    } else {
+        Ok(())
+    };
+    validation.map_err(|err: ApiError| err.request_id(&request_id))?;
+
+    let addresses = web::block(move || -> Result<_, ApiError> {
+        let conn = pool.get()?;
+        let addresses = search_addresses(&conn, country.as_deref(), &terms, limit)?;
+        if addresses.is_empty() && !has_state(&conn)? {
+            return Err(ApiError::data_not_ready());
+        }
+        Ok(addresses)
+    })
+    .await
+    .map_err(|err| ApiError::from(err).request_id(&request_id))?;
+
+    Ok(HttpResponse::Ok().json(addresses))
+}
+
+fn address_query(request: AddressRequest, max_limit: i64) -> Result<AddressQuery, ApiError> {
+    let AddressRequest {
+        country,
+        postcode,
+        number,
+        letter,
+        addition,
+        match_mode,
+        limit,
+        cursor
+    } = request;
+    let country = country.map(|c| c.trim().to_uppercase());
+    let postcode = normalize_postcode(country.as_deref(), &postcode)?;
+    let filter = house_number_filter(number, letter, addition, match_mode)?;
+
+    let limit = limit.unwrap_or(max_limit);
+    if !(1..=max_limit).contains(&limit) {
+        return Err(ApiError::invalid_query(
+            "limit",
+            format!("limit must be between 1 and {}", max_limit)
+        ));
+    }
+    let after = match cursor.filter(|c| !c.is_empty()) {
+        Some(cursor) => Some(
+            AddressCursor::decode(&cursor)
+                .ok_or_else(|| ApiError::invalid_query("cursor", "Invalid cursor"))?
+        ),
+        None => None,
+    };
+
+    Ok(AddressQuery { country, postcode, filter, limit, after })
+}
+
+/// The number parameter can contain the letter and addition (e.g. "12A-1"),
+/// the letter and addition parameters take precedence over them.
+fn house_number_filter(
+    number: Option<String>,
+    letter: Option<String>,
+    addition: Option<String>,
+    match_mode: Option<MatchMode>
+) -> Result<HouseNumberFilter, ApiError> {
+    let parsed = match number.as_deref().map(str::trim).filter(|n| !n.is_empty()) {
+        Some(value) => Some(
+            parse_house_number(value)
+                .ok_or_else(|| ApiError::invalid_query("number", "number must start with digits"))?
+        ),
+        None => None,
+    };
+    let non_empty = |value: Option<String>| {
+        value
+            .map(|v| v.trim().to_owned())
+            .filter(|v| !v.is_empty())
+    };
+
+    Ok(HouseNumberFilter {
+        number: parsed.as_ref().map(|p| p.number),
+        letter: non_empty(letter).or_else(|| parsed.as_ref().and_then(|p| p.letter.clone())),
+        addition: non_empty(addition).or_else(|| parsed.and_then(|p| p.addition)),
+        mode: match_mode.unwrap_or(MatchMode::Prefix)
+    })
+}
+
+/// Splits free text into lowercase alphanumeric search terms.
+/// Digits followed by letters forming a postcode (e.g. "1011 PN") are joined,
+/// since postcodes are stored without spaces. Other adjacent words are kept
+/// apart, a house number followed by a postcode could otherwise be taken
+/// for a postcode as well.
+pub fn search_terms(text: &str) -> Vec<String> {
+    let words = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<String>>();
+
+    let mut terms = Vec::with_capacity(words.len());
+    let mut i = 0;
+    while i < words.len() {
+        let split_postcode = i + 1 < words.len()
+            && words[i].chars().all(|c| c.is_ascii_digit())
+            && words[i + 1].chars().all(char::is_alphabetic);
+        if split_postcode {
+            let joined = format!("{}{}", words[i], words[i + 1]);
+            if normalize_postcode(None, &joined).is_ok() {
+                terms.push(joined);
+                i += 2;
+                continue;
+            }
+        }
+        terms.push(words[i].clone());
+        i += 1;
     }
+
+    terms
 }
@@ -0,0 +1,153 @@
+use actix_web::{FromRequest, HttpRequest, HttpResponse, web};
+use actix_web::dev::Payload;
+use actix_web::http::header::AUTHORIZATION;
+use futures::future::{err, ok, Ready};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::api::error::ApiError;
+use crate::api::request_id::RequestId;
+use crate::config::ApiConfig;
+use crate::data::state::control::{RefreshCommand, RefreshControl};
+use crate::data::state::monitor::{CurrentRefresh, RefreshMonitor, RefreshPhase};
+use crate::metrics::{IMPORT_RECORDS_EXPECTED, IMPORT_RECORDS_READ};
+use crate::utils::ExistsExtension;
+
+const BEARER_PREFIX: &str = "Bearer ";
+
+/// Extracted from requests bearing the admin token, in an
+/// `Authorization: Bearer <token>` header. Required by admin endpoints.
+pub struct Admin;
+
+impl FromRequest for Admin {
+    type Config = ();
+    type Error = ApiError;
+    type Future = Ready<Result<Self, ApiError>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let admin_token = req
+            .app_data::<web::Data<ApiConfig>>()
+            .and_then(|config| config.admin_token.clone());
+        let provided_token = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| value.starts_with(BEARER_PREFIX))
+            .map(|value| value[BEARER_PREFIX.len()..].trim());
+
+        match (admin_token, provided_token) {
+            (Some(expected), Some(provided)) if constant_time_eq(&expected, provided) => ok(Admin),
+            _ => err(ApiError::unauthorized().request_id(&RequestId::from_request_head(req))),
+        }
+    }
+}
+
+/// Doesn't stop at the first difference, so that the time taken
+/// doesn't tell how much of the token was guessed right
+fn constant_time_eq(expected: &str, provided: &str) -> bool {
+    expected.len() == provided.len() && expected
+        .bytes()
+        .zip(provided.bytes())
+        .fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    force: Option<bool>
+}
+
+#[derive(Serialize)]
+pub struct CurrentRefreshResponse {
+    refreshing: bool,
+    paused: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    current: Option<RefreshProgress>
+}
+
+#[derive(Serialize)]
+pub struct RefreshProgress {
+    #[serde(flatten)]
+    refresh: CurrentRefresh,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    records_read: Option<i64>,
+    /// Unknown for local data files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    records_expected: Option<i64>
+}
+
+/// Starts a data refresh, without waiting for it to complete.
+/// With `force`, the data is imported even when its version is the current one.
+pub async fn refresh(
+    _admin: Admin,
+    request_id: RequestId,
+    request: web::Query<RefreshRequest>,
+    control: web::Data<RefreshControl>,
+    monitor: web::Data<RefreshMonitor>
+) -> Result<HttpResponse, ApiError> {
+    if !monitor.request_refresh() {
+        return Err(ApiError::refresh_in_progress().request_id(&request_id));
+    }
+    let force = request.force.unwrap_or(false);
+    if let Err(error) = send_command(&control, RefreshCommand::Refresh { force }, &request_id) {
+        monitor.refresh_request_failed();
+        return Err(error);
+    }
+
+    Ok(HttpResponse::Accepted().json(json!({ "status": "refresh_requested", "force": force })))
+}
+
+/// Skips the scheduled refreshes, a refresh in progress is not interrupted
+pub async fn pause(
+    _admin: Admin,
+    request_id: RequestId,
+    control: web::Data<RefreshControl>
+) -> Result<HttpResponse, ApiError> {
+    send_command(&control, RefreshCommand::Pause, &request_id)?;
+    Ok(HttpResponse::Accepted().json(json!({ "status": "pause_requested" })))
+}
+
+pub async fn resume(
+    _admin: Admin,
+    request_id: RequestId,
+    control: web::Data<RefreshControl>
+) -> Result<HttpResponse, ApiError> {
+    send_command(&control, RefreshCommand::Resume, &request_id)?;
+    Ok(HttpResponse::Accepted().json(json!({ "status": "resume_requested" })))
+}
+
+/// Progress of the refresh in progress, if any
+pub async fn current_refresh(
+    _admin: Admin,
+    monitor: web::Data<RefreshMonitor>
+) -> HttpResponse {
+    let report = monitor.report();
+    let current = monitor.current().map(|refresh| {
+        // The import metrics are only reset once the records are being loaded
+        let importing = refresh.phase.exists(|phase| *phase != RefreshPhase::Downloading);
+        let (records_read, records_expected) = match &refresh.source {
+            Some(source) if importing => (
+                Some(IMPORT_RECORDS_READ.with_label_values(&[source]).get()),
+                Some(IMPORT_RECORDS_EXPECTED.with_label_values(&[source]).get())
+                    .filter(|count| *count > 0)
+            ),
+            _ => (None, None),
+        };
+        RefreshProgress { refresh, records_read, records_expected }
+    });
+
+    HttpResponse::Ok().json(CurrentRefreshResponse {
+        refreshing: report.refreshing,
+        paused: report.paused,
+        current
+    })
+}
+
+fn send_command(
+    control: &RefreshControl,
+    command: RefreshCommand,
+    request_id: &RequestId
+) -> Result<(), ApiError> {
+    control
+        .send(command)
+        .map_err(|_| ApiError::refresher_unavailable().request_id(request_id))
+}
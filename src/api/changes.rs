@@ -0,0 +1,69 @@
+use actix_web::{HttpResponse, web};
+use serde::Deserialize;
+
+use crate::api::{HAS_MORE_HEADER, NEXT_CURSOR_HEADER};
+use crate::api::error::ApiError;
+use crate::api::request_id::RequestId;
+use crate::data::repo::changes::get_changes;
+use crate::data::repo::states::find_state_by_version;
+use crate::db::Pool;
+use crate::postcode::{PostcodeError, rule_for};
+use crate::utils::ExistsExtension;
+
+const DEFAULT_CHANGES_LIMIT: i64 = 1000;
+const MAX_CHANGES_LIMIT: i64 = 10000;
+
+#[derive(Deserialize)]
+pub struct ChangesRequest {
+    since: String,
+    country: Option<String>,
+    limit: Option<i64>,
+    cursor: Option<String>
+}
+
+/// Address changes of the states imported after the `since` version,
+/// oldest first.
+pub async fn changes(
+    request_id: RequestId,
+    request: web::Query<ChangesRequest>,
+    pool: web::Data<Pool>
+) -> Result<HttpResponse, ApiError> {
+    let ChangesRequest { since, country, limit, cursor } = request.into_inner();
+    let limit = limit.unwrap_or(DEFAULT_CHANGES_LIMIT);
+    let country = country.map(|c| c.trim().to_uppercase());
+    let after = cursor.as_ref().map(|c| c.parse::<i64>());
+
+    let validation = if !(1..=MAX_CHANGES_LIMIT).contains(&limit) {
+        Err(ApiError::invalid_query(
+            "limit",
+            format!("limit must be between 1 and {}", MAX_CHANGES_LIMIT)
+        ))
+    } else if after.as_ref().exists(|a| a.is_err()) {
+        Err(ApiError::invalid_query("cursor", "Invalid cursor"))
+    } else if country.as_deref().exists(|c| rule_for(c).is_none()) {
+        Err(PostcodeError::UnsupportedCountry(country.clone().unwrap_or_default()).into())
+    } else {
+        Ok(())
+    };
+    validation.map_err(|err: ApiError| err.request_id(&request_id))?;
+    let after = after.and_then(Result::ok);
+
+    let page = web::block(move || -> Result<_, ApiError> {
+        let conn = pool.get()?;
+        let since_state = find_state_by_version(&conn, since.trim())?
+            .ok_or_else(|| ApiError::invalid_query(
+                "since",
+                format!("Unknown version {}", since.trim())
+            ))?;
+        Ok(get_changes(&conn, since_state.processed_at, country.as_deref(), limit, after)?)
+    })
+    .await
+    .map_err(|err| ApiError::from(err).request_id(&request_id))?;
+
+    let mut response = HttpResponse::Ok();
+    response.header(HAS_MORE_HEADER, page.next_cursor.is_some().to_string());
+    if let Some(cursor) = page.next_cursor {
+        response.header(NEXT_CURSOR_HEADER, cursor.to_string());
+    }
+    Ok(response.json(page.changes))
+}
@@ -0,0 +1,212 @@
+use std::fmt::Formatter;
+
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use actix_web::error::{BlockingError, JsonPayloadError, QueryPayloadError};
+use actix_web::http::StatusCode;
+use log::error;
+use serde::Serialize;
+
+use crate::api::request_id::RequestId;
+use crate::postcode::PostcodeError;
+
+/// Stable error codes, part of the API contract.
+/// Existing codes must not be renamed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidQuery,
+    InvalidBody,
+    EmptyPostcode,
+    InvalidPostcodeFormat,
+    UnsupportedCountry,
+    NotFound,
+    Unauthorized,
+    RefreshInProgress,
+    DatabaseUnavailable,
+    DataNotReady,
+    RefresherUnavailable,
+    InternalError,
+}
+
+impl ErrorCode {
+    pub fn status_code(self) -> StatusCode {
+        match self {
+            ErrorCode::InvalidQuery
+            | ErrorCode::InvalidBody
+            | ErrorCode::EmptyPostcode
+            | ErrorCode::InvalidPostcodeFormat
+            | ErrorCode::UnsupportedCountry => StatusCode::BAD_REQUEST,
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::RefreshInProgress => StatusCode::CONFLICT,
+            ErrorCode::DatabaseUnavailable
+            | ErrorCode::DataNotReady
+            | ErrorCode::RefresherUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Code as serialized in the JSON body
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::InvalidQuery => "invalid_query",
+            ErrorCode::InvalidBody => "invalid_body",
+            ErrorCode::EmptyPostcode => "empty_postcode",
+            ErrorCode::InvalidPostcodeFormat => "invalid_postcode_format",
+            ErrorCode::UnsupportedCountry => "unsupported_country",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::RefreshInProgress => "refresh_in_progress",
+            ErrorCode::DatabaseUnavailable => "database_unavailable",
+            ErrorCode::DataNotReady => "data_not_ready",
+            ErrorCode::RefresherUnavailable => "refresher_unavailable",
+            ErrorCode::InternalError => "internal_error",
+        }
+    }
+}
+
+/// Error returned by the HTTP API, rendered as a JSON body:
+/// `{"code": "...", "message": "...", "field": "...", "request_id": "..."}`
+#[derive(Debug, Serialize)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>
+}
+
+impl ApiError {
+    pub fn new<M: Into<String>>(code: ErrorCode, message: M) -> Self {
+        Self { code, message: message.into(), field: None, request_id: None }
+    }
+
+    pub fn field<F: Into<String>>(mut self, field: F) -> Self {
+        self.field = Some(field.into());
+        self
+    }
+
+    pub fn request_id(mut self, request_id: &RequestId) -> Self {
+        self.request_id = Some(request_id.0.clone());
+        self
+    }
+
+    pub fn invalid_query<M: Into<String>>(field: &str, message: M) -> Self {
+        ApiError::new(ErrorCode::InvalidQuery, message).field(field)
+    }
+
+    pub fn invalid_body<M: Into<String>>(message: M) -> Self {
+        ApiError::new(ErrorCode::InvalidBody, message)
+    }
+
+    pub fn not_found() -> Self {
+        ApiError::new(ErrorCode::NotFound, "Resource not found")
+    }
+
+    pub fn data_not_ready() -> Self {
+        ApiError::new(ErrorCode::DataNotReady, "Address data has not been imported yet")
+    }
+
+    pub fn unauthorized() -> Self {
+        ApiError::new(ErrorCode::Unauthorized, "A valid admin token is required")
+    }
+
+    pub fn refresh_in_progress() -> Self {
+        ApiError::new(ErrorCode::RefreshInProgress, "A data refresh is already in progress")
+    }
+
+    pub fn refresher_unavailable() -> Self {
+        ApiError::new(ErrorCode::RefresherUnavailable, "Data refresh is disabled on this instance")
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code.as_str(), self.message)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.code.status_code()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(self)
+    }
+}
+
+impl From<PostcodeError> for ApiError {
+    fn from(error: PostcodeError) -> Self {
+        let (code, field) = match error {
+            PostcodeError::Empty => (ErrorCode::EmptyPostcode, "postcode"),
+            PostcodeError::InvalidFormat(_) => (ErrorCode::InvalidPostcodeFormat, "postcode"),
+            PostcodeError::UnsupportedCountry(_) => (ErrorCode::UnsupportedCountry, "country"),
+        };
+        ApiError::new(code, error.to_string()).field(field)
+    }
+}
+
+impl From<r2d2::Error> for ApiError {
+    fn from(error: r2d2::Error) -> Self {
+        error!("Could not get a database connection: {}", error);
+        ApiError::new(ErrorCode::DatabaseUnavailable, "Database is unavailable")
+    }
+}
+
+impl From<diesel::result::Error> for ApiError {
+    fn from(error: diesel::result::Error) -> Self {
+        use diesel::result::{DatabaseErrorKind, Error};
+
+        error!("Database error: {}", error);
+        match error {
+            Error::DatabaseError(DatabaseErrorKind::UnableToSendCommand, _) =>
+                ApiError::new(ErrorCode::DatabaseUnavailable, "Database is unavailable"),
+            _ =>
+                ApiError::new(ErrorCode::InternalError, "Internal server error"),
+        }
+    }
+}
+
+impl <E> From<BlockingError<E>> for ApiError
+    where
+        E: Into<ApiError> + std::fmt::Debug,
+{
+    fn from(error: BlockingError<E>) -> Self {
+        match error {
+            BlockingError::Error(inner) => inner.into(),
+            BlockingError::Canceled => {
+                error!("Blocking operation canceled");
+                ApiError::new(ErrorCode::InternalError, "Internal server error")
+            },
+        }
+    }
+}
+
+/// Error handler for query string deserialization, registered as the
+/// `QueryConfig` of every route.
+pub fn query_error_handler(error: QueryPayloadError, req: &HttpRequest) -> actix_web::Error {
+    let QueryPayloadError::Deserialize(inner) = error;
+    let message = inner.to_string();
+    let mut api_error = ApiError::new(ErrorCode::InvalidQuery, message.as_str())
+        .request_id(&RequestId::from_request_head(req));
+
+    // serde reports missing fields between backticks,
+    // e.g. "missing field `postcode`"
+    if message.starts_with("missing field") {
+        if let Some(field) = message.split('`').nth(1) {
+            api_error = api_error.field(field);
+        }
+    }
+
+    api_error.into()
+}
+
+/// Error handler for JSON bodies, registered as the `JsonConfig`
+/// of every route accepting one.
+pub fn json_error_handler(error: JsonPayloadError, req: &HttpRequest) -> actix_web::Error {
+    ApiError::invalid_body(error.to_string())
+        .request_id(&RequestId::from_request_head(req))
+        .into()
+}
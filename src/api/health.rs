@@ -0,0 +1,63 @@
+use actix_web::{HttpResponse, web};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::api::error::ApiError;
+use crate::api::request_id::RequestId;
+use crate::data::models::{RegionCount, State};
+use crate::data::repo::addresses::count_addresses_by_region;
+use crate::data::repo::states::{current_states, has_state};
+use crate::data::state::monitor::{RefreshMonitor, RefreshReport};
+use crate::db::Pool;
+
+#[derive(Serialize)]
+pub struct StatusResponse {
+    /// Current state of every imported source
+    states: Vec<State>,
+    address_counts: Vec<RegionCount>,
+    refresh: RefreshReport
+}
+
+/// Alive as long as the process answers, regardless of the database
+pub async fn liveness() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+/// Ready once the database is reachable and data has been imported.
+/// Until then, lookups answer with a `data_not_ready` error.
+pub async fn readiness(
+    request_id: RequestId,
+    pool: web::Data<Pool>
+) -> Result<HttpResponse, ApiError> {
+    web::block(move || -> Result<(), ApiError> {
+        let conn = pool.get()?;
+        if !has_state(&conn)? {
+            return Err(ApiError::data_not_ready());
+        }
+        Ok(())
+    })
+    .await
+    .map_err(|err| ApiError::from(err).request_id(&request_id))?;
+
+    Ok(HttpResponse::Ok().json(json!({ "status": "ready" })))
+}
+
+/// Imported data, and the outcome of the last refresh
+pub async fn status(
+    request_id: RequestId,
+    pool: web::Data<Pool>,
+    monitor: web::Data<RefreshMonitor>
+) -> Result<HttpResponse, ApiError> {
+    let (states, address_counts) = web::block(move || -> Result<_, ApiError> {
+        let conn = pool.get()?;
+        Ok((current_states(&conn)?, count_addresses_by_region(&conn)?))
+    })
+    .await
+    .map_err(|err| ApiError::from(err).request_id(&request_id))?;
+
+    Ok(HttpResponse::Ok().json(StatusResponse {
+        states,
+        address_counts,
+        refresh: monitor.report()
+    }))
+}
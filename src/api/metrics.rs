@@ -0,0 +1,99 @@
+use std::future::Future;
+use std::time::Instant;
+
+use actix_web::{HttpResponse, web};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use chrono::Utc;
+use futures::FutureExt;
+use log::error;
+use prometheus::{Encoder, TextEncoder};
+
+use crate::api::error::{ApiError, ErrorCode};
+use crate::api::request_id::RequestId;
+use crate::data::repo::states::current_states;
+use crate::db::Pool;
+use crate::metrics::{
+    DB_POOL_CONNECTIONS,
+    DB_POOL_IDLE_CONNECTIONS,
+    DB_POOL_MAX_CONNECTIONS,
+    HTTP_REQUEST_DURATION,
+    HTTP_REQUESTS,
+    STATE_AGE,
+};
+
+// Label of the requests to unknown paths, which would otherwise
+// create a new series for every path
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Middleware recording the number and duration of requests,
+/// by route, method and status
+pub fn track_request<S, B>(
+    req: ServiceRequest,
+    srv: &mut S
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+    where
+        S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let start = Instant::now();
+    let route = if req.resource_map().has_resource(req.path()) {
+        req.path().to_owned()
+    } else {
+        UNMATCHED_ROUTE.to_owned()
+    };
+    let method = req.method().to_string();
+
+    srv.call(req).map(move |res| {
+        let status = match &res {
+            Ok(res) => res.status().as_u16().to_string(),
+            Err(err) => err.as_response_error().status_code().as_u16().to_string(),
+        };
+        let labels = [route.as_str(), method.as_str(), status.as_str()];
+        HTTP_REQUESTS.with_label_values(&labels).inc();
+        HTTP_REQUEST_DURATION
+            .with_label_values(&labels)
+            .observe(start.elapsed().as_secs_f64());
+        res
+    })
+}
+
+/// Metrics in the Prometheus text format
+pub async fn metrics(
+    request_id: RequestId,
+    pool: web::Data<Pool>
+) -> Result<HttpResponse, ApiError> {
+    let pool_state = pool.state();
+    DB_POOL_CONNECTIONS.set(i64::from(pool_state.connections));
+    DB_POOL_IDLE_CONNECTIONS.set(i64::from(pool_state.idle_connections));
+    DB_POOL_MAX_CONNECTIONS.set(i64::from(pool.max_size()));
+
+    // The other metrics are still useful while the database is unavailable
+    let states = web::block(move || -> Result<_, ApiError> {
+        let conn = pool.get()?;
+        Ok(current_states(&conn)?)
+    })
+    .await;
+    match states {
+        Ok(states) => {
+            let now = Utc::now().naive_utc();
+            for state in states {
+                STATE_AGE
+                    .with_label_values(&[&state.source])
+                    .set(now.signed_duration_since(state.processed_at).num_seconds());
+            }
+        },
+        Err(err) => { error!("Couldn't get the current states: {}", err); },
+    }
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    encoder
+        .encode(&prometheus::gather(), &mut buffer)
+        .map_err(|err| {
+            error!("Couldn't encode metrics: {}", err);
+            ApiError::new(ErrorCode::InternalError, "Internal server error").request_id(&request_id)
+        })?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(encoder.format_type())
+        .body(buffer))
+}
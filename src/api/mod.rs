@@ -1 +1,127 @@
+use actix_web::{HttpResponse, web};
+
+use crate::api::admin::{current_refresh, pause, refresh, resume};
+use crate::api::addresses::{
+    addresses,
+    autocomplete_addresses,
+    batch_addresses,
+    nearest_addresses,
+};
+use crate::api::changes::changes;
+use crate::api::error::{ApiError, json_error_handler, query_error_handler};
+use crate::api::health::{liveness, readiness, status};
+use crate::api::metrics::metrics;
+use crate::api::request_id::RequestId;
+use crate::api::verify::verify_address;
+use crate::config::ApiConfig;
+
 pub mod addresses;
+pub mod admin;
+pub mod changes;
+pub mod error;
+pub mod health;
+pub mod metrics;
+pub mod request_id;
+pub mod verify;
+
+// Pagination of list endpoints
+pub const HAS_MORE_HEADER: &str = "x-has-more";
+pub const NEXT_CURSOR_HEADER: &str = "x-next-cursor";
+
+// Large enough for a full batch of address queries
+const JSON_PAYLOAD_LIMIT: usize = 1024 * 1024;
+
+pub fn configure(config: ApiConfig) -> impl FnOnce(&mut web::ServiceConfig) {
+    move |cfg| {
+        let admin = config.admin_token.is_some();
+        cfg.data(config);
+        configure_routes(cfg);
+        if admin {
+            configure_admin_routes(cfg);
+        }
+    }
+}
+
+fn configure_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/addresses")
+            .app_data(query_config())
+            .route(web::get().to(addresses))
+    );
+    cfg.service(
+        web::resource("/addresses/batch")
+            .app_data(json_config())
+            .route(web::post().to(batch_addresses))
+    );
+    cfg.service(
+        web::resource("/addresses/nearest")
+            .app_data(query_config())
+            .route(web::get().to(nearest_addresses))
+    );
+    cfg.service(
+        web::resource("/addresses/autocomplete")
+            .app_data(query_config())
+            .route(web::get().to(autocomplete_addresses))
+    );
+    cfg.service(
+        web::resource("/verify")
+            .app_data(json_config())
+            .route(web::post().to(verify_address))
+    );
+    cfg.service(
+        web::resource("/changes")
+            .app_data(query_config())
+            .route(web::get().to(changes))
+    );
+    cfg.service(
+        web::resource("/healthz")
+            .route(web::get().to(liveness))
+    );
+    cfg.service(
+        web::resource("/readyz")
+            .route(web::get().to(readiness))
+    );
+    cfg.service(
+        web::resource("/status")
+            .route(web::get().to(status))
+    );
+    cfg.service(
+        web::resource("/metrics")
+            .route(web::get().to(metrics))
+    );
+}
+
+/// Only available when an admin token is configured
+fn configure_admin_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/admin/refresh")
+            .app_data(query_config())
+            .route(web::post().to(refresh))
+    );
+    cfg.service(
+        web::resource("/admin/refresh/current")
+            .route(web::get().to(current_refresh))
+    );
+    cfg.service(
+        web::resource("/admin/refresher/pause")
+            .route(web::post().to(pause))
+    );
+    cfg.service(
+        web::resource("/admin/refresher/resume")
+            .route(web::post().to(resume))
+    );
+}
+
+fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default().error_handler(query_error_handler)
+}
+
+fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(JSON_PAYLOAD_LIMIT)
+        .error_handler(json_error_handler)
+}
+
+pub async fn not_found(request_id: RequestId) -> Result<HttpResponse, ApiError> {
+    Err(ApiError::not_found().request_id(&request_id))
+}
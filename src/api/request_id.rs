@@ -0,0 +1,53 @@
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest};
+use actix_web::dev::{Extensions, Payload, ServiceRequest};
+use actix_web::http::HeaderMap;
+use futures::future::{ok, Ready};
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// Identifier of a request, included in error responses and in the
+/// `X-Request-Id` response header.
+/// It is taken from the `X-Request-Id` request header when present
+/// (e.g. set by a load balancer), or generated otherwise.
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    pub fn from_service_request(req: &ServiceRequest) -> Self {
+        Self::get_or_create(req.headers(), &mut req.extensions_mut())
+    }
+
+    pub fn from_request_head(req: &HttpRequest) -> Self {
+        Self::get_or_create(req.headers(), &mut req.extensions_mut())
+    }
+
+    fn get_or_create(headers: &HeaderMap, extensions: &mut Extensions) -> Self {
+        if let Some(request_id) = extensions.get::<RequestId>() {
+            return request_id.clone();
+        }
+
+        let id = headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty() && value.len() <= MAX_REQUEST_ID_LENGTH)
+            .map(str::to_owned)
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+        let request_id = RequestId(id);
+        extensions.insert(request_id.clone());
+        request_id
+    }
+}
+
+impl FromRequest for RequestId {
+    type Config = ();
+    type Error = Error;
+    type Future = Ready<Result<Self, Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ok(RequestId::from_request_head(req))
+    }
+}
@@ -0,0 +1,162 @@
+use actix_web::{HttpResponse, web};
+use diesel::PgConnection;
+use serde::Deserialize;
+
+use crate::api::addresses::search_terms;
+use crate::api::error::ApiError;
+use crate::api::request_id::RequestId;
+use crate::config::ApiConfig;
+use crate::data::house_number::parse_house_number;
+use crate::data::models::Address;
+use crate::data::repo::addresses::{
+    get_addresses,
+    HouseNumberFilter,
+    MatchMode,
+    search_addresses,
+};
+use crate::data::repo::states::has_state;
+use crate::db::Pool;
+use crate::postcode::{normalize_postcode, PostcodeError, rule_for};
+use crate::utils::ExistsExtension;
+use crate::verification::{SubmittedAddress, Verdict, verify};
+
+const MAX_SEARCH_CANDIDATES: i64 = 20;
+
+#[derive(Deserialize)]
+pub struct VerifyRequest {
+    country: Option<String>,
+    street: Option<String>,
+    number: String,
+    postcode: Option<String>,
+    city: Option<String>
+}
+
+pub async fn verify_address(
+    request_id: RequestId,
+    request: web::Json<VerifyRequest>,
+    pool: web::Data<Pool>,
+    config: web::Data<ApiConfig>
+) -> Result<HttpResponse, ApiError> {
+    let VerifyRequest { country, street, number, postcode, city } = request.into_inner();
+    let country = country.map(|c| c.trim().to_uppercase());
+    let non_empty = |value: Option<String>| value.filter(|v| !v.trim().is_empty());
+    let submitted = SubmittedAddress {
+        street: non_empty(street),
+        number,
+        postcode: non_empty(postcode),
+        city: non_empty(city)
+    };
+
+    let validation = if submitted.number.trim().is_empty() {
+        Err(ApiError::invalid_body("number must not be empty").field("number"))
+    } else if submitted.postcode.is_none() && submitted.street.is_none() {
+        Err(ApiError::invalid_body("Either postcode or street is required").field("postcode"))
+    } else if country.as_deref().exists(|c| rule_for(c).is_none()) {
+        Err(PostcodeError::UnsupportedCountry(country.clone().unwrap_or_default()).into())
+    } else {
+        Ok(())
+    };
+    validation.map_err(|err: ApiError| err.request_id(&request_id))?;
+
+    let max_limit = config.addresses_max_limit;
+    let verification = web::block(move || -> Result<_, ApiError> {
+        let conn = pool.get()?;
+        let candidates = find_candidates(&conn, country.as_deref(), &submitted, max_limit)?;
+        let verification = verify(&submitted, candidates);
+        if verification.verdict == Verdict::NotFound && !has_state(&conn)? {
+            return Err(ApiError::data_not_ready());
+        }
+        Ok(verification)
+    })
+    .await
+    .map_err(|err| ApiError::from(err).request_id(&request_id))?;
+
+    Ok(HttpResponse::Ok().json(verification))
+}
+
+/// Addresses the submitted one could refer to. The postcode is tried first,
+/// then a full text search in case the postcode is wrong or missing,
+/// with fewer terms each time to account for typos, and finally the area
+/// of the postcode with the number.
+fn find_candidates(
+    conn: &PgConnection,
+    country: Option<&str>,
+    submitted: &SubmittedAddress,
+    max_limit: i64
+) -> Result<Vec<Address>, diesel::result::Error> {
+    let parsed_number = parse_house_number(&submitted.number);
+
+    let postcode = submitted
+        .postcode
+        .as_ref()
+        .and_then(|p| normalize_postcode(country, p).ok());
+    if let Some(postcode) = postcode {
+        let filter = HouseNumberFilter {
+            number: parsed_number.as_ref().map(|n| n.number),
+            letter: None,
+            addition: None,
+            mode: MatchMode::Prefix
+        };
+        let candidates = get_addresses(
+            conn,
+            country,
+            &postcode,
+            &filter,
+            max_limit,
+            None
+        )?;
+        if !candidates.addresses.is_empty() {
+            return Ok(candidates.addresses);
+        }
+    }
+
+    let number = parsed_number
+        .map(|n| n.number.to_string())
+        .unwrap_or_else(|| submitted.number.clone());
+    let fields = |fields: &[Option<&String>]| {
+        fields
+            .iter()
+            .filter_map(|f| f.map(|s| s.as_str()))
+            .collect::<Vec<&str>>()
+            .join(" ")
+    };
+    let postcode_area = submitted.postcode.as_deref().and_then(postcode_area);
+    let searches = [
+        fields(&[submitted.street.as_ref(), Some(&number), submitted.city.as_ref()]),
+        fields(&[Some(&number), submitted.city.as_ref()]),
+        fields(&[submitted.street.as_ref(), Some(&number)]),
+        // Typos in both the street and the city, the area of a wrong postcode is often right
+        fields(&[postcode_area.as_ref(), Some(&number)]),
+    ];
+    for search in searches.iter() {
+        let terms = search_terms(search);
+        // The number alone would match far too many addresses
+        if terms.len() < 2 {
+            continue;
+        }
+        let candidates = search_addresses(conn, country, &terms, MAX_SEARCH_CANDIDATES)?;
+        if !candidates.is_empty() {
+            return Ok(candidates.into_iter().map(|c| c.address).collect());
+        }
+    }
+
+    Ok(vec![])
+}
+
+/// Leading digits of the postcode, without the last one when it only has digits
+/// (`1011` for `1011 PN`, `1234` for `12345`)
+fn postcode_area(postcode: &str) -> Option<String> {
+    let compact = postcode
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>();
+    let mut digits = compact
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect::<String>();
+    if digits.len() == compact.len() {
+        digits.pop();
+    }
+
+    if digits.len() < 2 { None } else { Some(digits) }
+}
@@ -1,19 +1,54 @@
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
     use actix_web::{
         App,
         dev::Service,
-        http::StatusCode, test, web,
+        http::StatusCode, HttpResponse, test, web,
     };
-    use diesel::RunQueryDsl;
-    use futures::FutureExt;
+    use diesel::{PgConnection, RunQueryDsl};
+    use futures::{FutureExt, StreamExt};
 
     use lazy_static::lazy_static;
+    use structopt::StructOpt;
+    use uuid::Uuid;
 
-    use crate::addresses;
-    use crate::data::models::{Address, AddressRecord};
-    use crate::data::repo::addresses::create_or_update_addresses;
-    use crate::db::{init_test_connection_pool, Pool};
+    use crate::api::configure;
+    use crate::api::error::{ApiError, ErrorCode};
+    use crate::api::metrics::track_request;
+    use crate::cli::{Cli, Command, CommandError, import_location, run};
+    use crate::config::{ApiConfig, Config, ConfigError, DataConfig, DatabaseConfig};
+    use crate::data::models::{Address, AddressChange, AddressRecord, NearestAddress, RankedAddress};
+    use crate::data::repo::addresses::{
+        clear_staged_addresses,
+        count_addresses_by_region,
+        count_staged_addresses,
+        ImportCounts,
+        publish_staged_addresses,
+        update_region_counts
+    };
+    use crate::data::repo::bulk_load::BulkLoader;
+    use crate::data::repo::import_lock::ImportLock;
+    use crate::data::repo::states::{create_new_state, current_state};
+    use crate::data::state::{
+        publish_state,
+        recover_interrupted_imports,
+        refresh_state,
+        RefreshConfig,
+        StateInfo
+    };
+    use crate::data::state::download::download_to_file;
+    use crate::data::state::error::RefreshError;
+    use crate::data::state::location::{DataLocation, resolve_mirror_path};
+    use crate::data::state::monitor::RefreshMonitor;
+    use crate::data::state::remote::RemoteConfig;
+    use crate::data::state::retry::RetryPolicy;
+    use crate::data::state::shutdown::Shutdown;
+    use crate::data::state::source::Source;
+    use crate::data::state::state_refresher::StateRefresher;
+    use crate::db::{init_test_connection_pool, Pool, test_database_url};
 
     embed_migrations!("./migrations");
 
@@ -42,7 +77,7 @@ mod tests {
     }
 
     async fn setup() {
-        use crate::data::schema::addresses;
+        use crate::data::schema::{addresses, region_counts, states};
         // Clear data from previous tests
         web::block(|| {
             diesel::delete(addresses::table)
@@ -50,6 +85,20 @@ mod tests {
         })
         .await
         .expect("Couldn't delete addresses table");
+        web::block(|| {
+            diesel::delete(region_counts::table)
+                .execute(&POOL.get().unwrap())
+        })
+        .await
+        .expect("Couldn't delete region_counts table");
+        web::block(|| {
+            diesel::delete(states::table)
+                .execute(&POOL.get().unwrap())
+        })
+        .await
+        .expect("Couldn't delete states table");
+        // Most tests expect data to be available
+        create_test_state().await;
     }
 
     async fn teardown () {}
@@ -60,7 +109,7 @@ mod tests {
             let mut app = test::init_service(
                 App::new()
                     .data(POOL.clone())
-                    .route("/addresses", web::get().to(addresses))
+                    .configure(configure(ApiConfig::default()))
             )
             .await;
 
@@ -70,6 +119,11 @@ mod tests {
 
             let resp = app.call(req).await.unwrap();
             assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            let body = test::read_body(resp).await;
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["code"], "invalid_query");
+            assert_eq!(body["field"], "postcode");
+            assert!(body["request_id"].is_string());
         })
         .await
     }
@@ -80,7 +134,7 @@ mod tests {
             let mut app = test::init_service(
                 App::new()
                     .data(POOL.clone())
-                    .route("/addresses", web::get().to(addresses))
+                    .configure(configure(ApiConfig::default()))
             )
             .await;
 
@@ -100,7 +154,7 @@ mod tests {
             let mut app = test::init_service(
                 App::new()
                     .data(POOL.clone())
-                    .route("/addresses", web::get().to(addresses))
+                    .configure(configure(ApiConfig::default()))
             )
             .await;
 
@@ -122,7 +176,7 @@ mod tests {
             let mut app = test::init_service(
                 App::new()
                     .data(POOL.clone())
-                    .route("/addresses", web::get().to(addresses))
+                    .configure(configure(ApiConfig::default()))
             )
             .await;
 
@@ -171,10 +225,2198 @@ mod tests {
         .await
     }
 
+    #[actix_rt::test]
+    async fn test_get_addresses_country() {
+        run_test(async {
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            create_test_set().await;
+            create_be_test_set().await;
+
+            let req = test::TestRequest::get()
+                .uri("/addresses?postcode=1000&country=be")
+                .to_request();
+
+            let resp: Vec<Address> = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.len(), 1);
+            assert_eq!(resp[0].postcode, "1000");
+            assert_eq!(resp[0].country, "BE");
+
+            let req = test::TestRequest::get()
+                .uri("/addresses?postcode=1000&country=DK")
+                .to_request();
+
+            let resp: Vec<Address> = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.len(), 0);
+
+            let req = test::TestRequest::get()
+                .uri("/addresses?postcode=2222AA&country=NL")
+                .to_request();
+
+            let resp: Vec<Address> = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.len(), 4);
+            assert!(resp.iter().all(|a| a.country == "NL"));
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_get_addresses_normalized_postcode() {
+        run_test(async {
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            create_test_set().await;
+
+            for postcode in &["2222AA", "2222aa", "2222%20aa", "%202222Aa%20"] {
+                let req = test::TestRequest::get()
+                    .uri(&format!("/addresses?postcode={}&number=1", postcode))
+                    .to_request();
+
+                let resp: Vec<Address> = test::read_response_json(&mut app, req).await;
+                assert_eq!(resp.len(), 1);
+                assert_eq!(resp[0].postcode, "2222AA");
+            }
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_get_addresses_invalid_postcode() {
+        run_test(async {
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            let cases = [
+                ("postcode=0222AA&country=NL", "invalid_postcode_format", "postcode"),
+                ("postcode=2222SA&country=NL", "invalid_postcode_format", "postcode"),
+                ("postcode=2222AA&country=BE", "invalid_postcode_format", "postcode"),
+                ("postcode=ABCDEF", "invalid_postcode_format", "postcode"),
+                ("postcode=%20", "empty_postcode", "postcode"),
+                ("postcode=2222AA&country=XX", "unsupported_country", "country"),
+            ];
+            for (query, code, field) in cases.iter() {
+                let req = test::TestRequest::get()
+                    .uri(&format!("/addresses?{}", query))
+                    .header("X-Request-Id", "test-request")
+                    .to_request();
+
+                let resp = app.call(req).await.unwrap();
+                assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+                let body = test::read_body(resp).await;
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                assert_eq!(body["code"], *code);
+                assert_eq!(body["field"], *field);
+                assert_eq!(body["request_id"], "test-request");
+                assert!(body["message"].is_string());
+            }
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_get_addresses_data_not_ready() {
+        run_test(async {
+            use crate::data::schema::states;
+
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            web::block(|| {
+                diesel::delete(states::table)
+                    .execute(&POOL.get().unwrap())
+            })
+            .await
+            .expect("Couldn't delete states table");
+
+            let req = test::TestRequest::get()
+                .uri("/addresses?postcode=2222AA")
+                .to_request();
+
+            let resp = app.call(req).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+            let body = test::read_body(resp).await;
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["code"], "data_not_ready");
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_readiness_without_data() {
+        run_test(async {
+            use crate::data::schema::states;
+
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            let req = test::TestRequest::get()
+                .uri("/readyz")
+                .to_request();
+
+            let resp = app.call(req).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+
+            web::block(|| {
+                diesel::delete(states::table)
+                    .execute(&POOL.get().unwrap())
+            })
+            .await
+            .expect("Couldn't delete states table");
+
+            // Unreachable upstream, without any fallback
+            let location = DataLocation::Remote(RemoteConfig {
+                state_info_url: "http://127.0.0.1:9/state.txt".to_string(),
+                retry: RetryPolicy { max_attempts: 1, ..RetryPolicy::default() },
+                ..RemoteConfig::default()
+            });
+            let result = test_refresh(&test_refresh_config(location)).await;
+            assert!(result.is_err());
+
+            let req = test::TestRequest::get()
+                .uri("/readyz")
+                .to_request();
+
+            let resp = app.call(req).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+            let body = test::read_body(resp).await;
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["code"], "data_not_ready");
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_health_and_status() {
+        run_test(async {
+            let monitor = RefreshMonitor::default();
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .data(monitor.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            create_test_set().await;
+            monitor.started(false);
+            monitor.finished(&Err(RefreshError::StateInfoUnavailable));
+            monitor.scheduled(std::time::Duration::from_secs(60));
+
+            let req = test::TestRequest::get()
+                .uri("/healthz")
+                .to_request();
+
+            let resp = app.call(req).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+
+            let req = test::TestRequest::get()
+                .uri("/status")
+                .to_request();
+
+            let resp = app.call(req).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = test::read_body(resp).await;
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+            let states = body["states"].as_array().unwrap();
+            let nl_state = states
+                .iter()
+                .find(|state| state["source"] == "nl/countrywide")
+                .expect("Missing nl/countrywide state");
+            assert_eq!(nl_state["version"], "1");
+            assert_eq!(nl_state["hash"], "hash");
+            assert!(nl_state["processed_at"].is_string());
+
+            let counts = body["address_counts"].as_array().unwrap();
+            assert_eq!(counts.len(), 1);
+            assert_eq!(counts[0]["country"], "NL");
+            assert_eq!(counts[0]["region"], "Region");
+            assert_eq!(counts[0]["count"], 4);
+
+            assert_eq!(body["refresh"]["refreshing"], false);
+            assert_eq!(body["refresh"]["last_result"], "failure");
+            assert!(body["refresh"]["last_error"].is_string());
+            assert!(body["refresh"]["last_attempt_at"].is_string());
+            assert!(body["refresh"]["last_success_at"].is_null());
+            assert!(body["refresh"]["next_refresh_at"].is_string());
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_metrics() {
+        run_test(async {
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .wrap_fn(track_request)
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            create_test_set().await;
+
+            let req = test::TestRequest::get()
+                .uri("/addresses?postcode=2222AA")
+                .to_request();
+
+            let resp = app.call(req).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+
+            let req = test::TestRequest::get()
+                .uri("/unknown/path")
+                .to_request();
+
+            let resp = app.call(req).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+            let req = test::TestRequest::get()
+                .uri("/metrics")
+                .to_request();
+
+            let resp = app.call(req).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert!(resp.headers().get("content-type").unwrap().to_str().unwrap().starts_with("text/plain"));
+            let body = test::read_body(resp).await;
+            let body = String::from_utf8(body.to_vec()).unwrap();
+
+            let has_line = |prefix: &str, labels: &[&str]| body
+                .lines()
+                .any(|line| line.starts_with(prefix) && labels.iter().all(|label| line.contains(label)));
+            assert!(has_line("http_requests_total{", &["route=\"/addresses\"", "method=\"GET\"", "status=\"200\""]));
+            assert!(has_line("http_request_duration_seconds_count{", &["route=\"/addresses\""]));
+            assert!(has_line("http_requests_total{", &["route=\"unmatched\"", "status=\"404\""]));
+            assert!(!body.contains("/unknown/path"));
+            assert!(has_line("address_query_duration_seconds_count{", &["query=\"postcode\""]));
+            assert!(has_line("db_pool_max_connections ", &[]));
+            assert!(has_line("state_age_seconds{", &["source=\"nl/countrywide\""]));
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_get_nearest_addresses() {
+        run_test(async {
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            create_test_set().await;
+
+            let req = test::TestRequest::get()
+                .uri("/addresses/nearest?lat=2.001&lon=1.0")
+                .to_request();
+
+            let resp: Vec<NearestAddress> = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.len(), 0);
+
+            let req = test::TestRequest::get()
+                .uri("/addresses/nearest?lat=2.001&lon=1.0&radius=500")
+                .to_request();
+
+            let resp: Vec<NearestAddress> = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.len(), 1);
+            assert_eq!(resp[0].address.number, "1");
+            assert!((resp[0].distance - 111.2).abs() < 1.0);
+
+            let req = test::TestRequest::get()
+                .uri("/addresses/nearest?lat=3.01&lon=2.0&radius=5000")
+                .to_request();
+
+            let resp: Vec<NearestAddress> = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.len(), 1);
+            assert_eq!(resp[0].address.number, "2");
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_get_nearest_addresses_ordering_and_limit() {
+        run_test(async {
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            web::block(|| {
+                import_addresses(
+                    &POOL.get().unwrap(),
+                    "NL",
+                    &[
+                        AddressRecord {
+                            lat: 52.3700,
+                            lon: 4.9000,
+                            number: "1".to_string(),
+                            street: "Street".to_string(),
+                            city: "City".to_string(),
+                            region: "Region".to_string(),
+                            postcode: "1011PN".to_string()
+                        },
+                        AddressRecord {
+                            lat: 52.3710,
+                            lon: 4.9000,
+                            number: "2".to_string(),
+                            street: "Street".to_string(),
+                            city: "City".to_string(),
+                            region: "Region".to_string(),
+                            postcode: "1011PN".to_string()
+                        },
+                        AddressRecord {
+                            lat: 52.3705,
+                            lon: 4.9000,
+                            number: "3".to_string(),
+                            street: "Street".to_string(),
+                            city: "City".to_string(),
+                            region: "Region".to_string(),
+                            postcode: "1011PN".to_string()
+                        },
+                    ]
+                )
+            })
+            .await
+            .expect("Error creating tests data");
+
+            let req = test::TestRequest::get()
+                .uri("/addresses/nearest?lat=52.3701&lon=4.9&radius=1000&limit=2")
+                .to_request();
+
+            let resp: Vec<NearestAddress> = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.len(), 2);
+            assert_eq!(resp[0].address.number, "1");
+            assert_eq!(resp[1].address.number, "3");
+            assert!(resp[0].distance < resp[1].distance);
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_get_nearest_addresses_invalid_query() {
+        run_test(async {
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            let cases = [
+                ("lon=4.9", "invalid_query", "lat"),
+                ("lat=91&lon=4.9", "invalid_query", "lat"),
+                ("lat=52&lon=-181", "invalid_query", "lon"),
+                ("lat=52&lon=4.9&radius=0", "invalid_query", "radius"),
+                ("lat=52&lon=4.9&radius=100000", "invalid_query", "radius"),
+                ("lat=52&lon=4.9&limit=0", "invalid_query", "limit"),
+                ("lat=52&lon=4.9&country=XX", "unsupported_country", "country"),
+            ];
+            for (query, code, field) in cases.iter() {
+                let req = test::TestRequest::get()
+                    .uri(&format!("/addresses/nearest?{}", query))
+                    .to_request();
+
+                let resp = app.call(req).await.unwrap();
+                assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+                let body = test::read_body(resp).await;
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                assert_eq!(body["code"], *code);
+                assert_eq!(body["field"], *field);
+            }
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_autocomplete_addresses() {
+        run_test(async {
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            create_amsterdam_test_set().await;
+
+            let req = test::TestRequest::get()
+                .uri("/addresses/autocomplete?q=Amstel%201%20Amst")
+                .to_request();
+
+            let resp: Vec<RankedAddress> = test::read_response_json(&mut app, req).await;
+            // "Ouderkerk aan de Amstel" also matches, through its city and postcode
+            assert_eq!(resp.len(), 4);
+            assert_eq!(resp[0].address.street, "Amstel");
+            assert_eq!(resp[0].address.number, "1");
+            assert!(resp.windows(2).all(|w| w[0].score >= w[1].score));
+
+            let req = test::TestRequest::get()
+                .uri("/addresses/autocomplete?q=Amstel%201%20Amsterdam")
+                .to_request();
+
+            let resp: Vec<RankedAddress> = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.len(), 3);
+            assert!(resp.iter().all(|a| a.address.city == "Amsterdam"));
+
+            let req = test::TestRequest::get()
+                .uri("/addresses/autocomplete?q=1011%20pn")
+                .to_request();
+
+            let resp: Vec<RankedAddress> = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.len(), 1);
+            assert_eq!(resp[0].address.postcode, "1011PN");
+
+            // The house number isn't joined with the postcode following it
+            let req = test::TestRequest::get()
+                .uri("/addresses/autocomplete?q=Amstel%201%201011%20PN")
+                .to_request();
+
+            let resp: Vec<RankedAddress> = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.len(), 1);
+            assert_eq!(resp[0].address.number, "1");
+            assert_eq!(resp[0].address.postcode, "1011PN");
+
+            let req = test::TestRequest::get()
+                .uri("/addresses/autocomplete?q=amstelveen&limit=1")
+                .to_request();
+
+            let resp: Vec<RankedAddress> = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.len(), 1);
+            assert_eq!(resp[0].address.street, "Amstelveenseweg");
+
+            let req = test::TestRequest::get()
+                .uri("/addresses/autocomplete?q=Amstel%201%20Rotterdam")
+                .to_request();
+
+            let resp: Vec<RankedAddress> = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.len(), 0);
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_autocomplete_addresses_invalid_query() {
+        run_test(async {
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            let cases = [
+                ("q=a", "q"),
+                ("q=%20%3A%26!", "q"),
+                ("q=amstel&limit=100", "limit"),
+            ];
+            for (query, field) in cases.iter() {
+                let req = test::TestRequest::get()
+                    .uri(&format!("/addresses/autocomplete?{}", query))
+                    .to_request();
+
+                let resp = app.call(req).await.unwrap();
+                assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+                let body = test::read_body(resp).await;
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                assert_eq!(body["code"], "invalid_query");
+                assert_eq!(body["field"], *field);
+            }
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_get_addresses_house_number_parts() {
+        run_test(async {
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            let numbers = ["1", "10", "100", "1A", "1-3", "12", "12 bis", "12-II", "12A-1", "12a"];
+            web::block(move || {
+                let records = numbers
+                    .iter()
+                    .map(|number| AddressRecord {
+                        lat: 52.0,
+                        lon: 4.0,
+                        number: number.to_string(),
+                        street: "Street".to_string(),
+                        city: "City".to_string(),
+                        region: "Region".to_string(),
+                        postcode: "3333BB".to_string()
+                    })
+                    .collect::<Vec<AddressRecord>>();
+                import_addresses(&POOL.get().unwrap(), "NL", &records)
+            })
+            .await
+            .expect("Error creating tests data");
+
+            let cases: [(&str, &[&str]); 11] = [
+                ("number=1", &["1", "1-3", "1A"]),
+                ("number=1&match=exact", &["1"]),
+                ("number=1&letter=a", &["1A"]),
+                ("number=1a", &["1A"]),
+                ("number=1&addition=3&match=exact", &["1-3"]),
+                ("number=12", &["12", "12 bis", "12-II", "12A-1", "12a"]),
+                ("number=12&addition=bis", &["12 bis"]),
+                ("number=12%20bis&match=exact", &["12 bis"]),
+                ("number=12-ii&match=exact", &["12-II"]),
+                ("number=12A&match=exact", &["12a"]),
+                ("number=12A-1&match=exact", &["12A-1"]),
+            ];
+            for (query, expected) in cases.iter() {
+                let req = test::TestRequest::get()
+                    .uri(&format!("/addresses?postcode=3333BB&{}", query))
+                    .to_request();
+
+                let resp: Vec<Address> = test::read_response_json(&mut app, req).await;
+                let mut numbers = resp.iter().map(|a| a.number.as_str()).collect::<Vec<&str>>();
+                numbers.sort();
+                assert_eq!(&numbers[..], *expected, "query: {}", query);
+            }
+
+            let req = test::TestRequest::get()
+                .uri("/addresses?postcode=3333BB&number=12A-1")
+                .to_request();
+
+            let resp: Vec<Address> = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.len(), 1);
+            assert_eq!(resp[0].house_number, Some(12));
+            assert_eq!(resp[0].letter.as_deref(), Some("A"));
+            assert_eq!(resp[0].addition.as_deref(), Some("1"));
+
+            for query in &["number=abc", "number=1&match=fuzzy"] {
+                let req = test::TestRequest::get()
+                    .uri(&format!("/addresses?postcode=3333BB&{}", query))
+                    .to_request();
+
+                let resp = app.call(req).await.unwrap();
+                assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            }
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_batch_addresses() {
+        run_test(async {
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            create_test_set().await;
+            create_be_test_set().await;
+
+            let req = test::TestRequest::post()
+                .uri("/addresses/batch")
+                .set_json(&serde_json::json!([
+                    { "postcode": "2222 aa", "number": "2" },
+                    { "postcode": "2222AA", "number": "3" },
+                    { "postcode": "invalid" },
+                    { "postcode": "1000", "country": "BE" },
+                    { "postcode": "2222AA", "number": "2A", "match": "exact" },
+                ]))
+                .to_request();
+
+            let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+            let results = resp.as_array().unwrap();
+            assert_eq!(results.len(), 5);
+
+            assert_eq!(results[0]["status"], "ok");
+            assert_eq!(results[0]["addresses"].as_array().unwrap().len(), 3);
+            assert_eq!(results[1]["status"], "not_found");
+            assert_eq!(results[1]["addresses"].as_array().unwrap().len(), 0);
+            assert_eq!(results[2]["status"], "invalid");
+            assert_eq!(results[2]["error"]["code"], "invalid_postcode_format");
+            assert_eq!(results[2]["error"]["field"], "postcode");
+            assert_eq!(results[3]["status"], "ok");
+            assert_eq!(results[3]["addresses"][0]["country"], "BE");
+            assert_eq!(results[4]["status"], "ok");
+            assert_eq!(results[4]["addresses"].as_array().unwrap().len(), 1);
+            assert_eq!(results[4]["addresses"][0]["number"], "2A");
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_batch_addresses_invalid_body() {
+        run_test(async {
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            let too_large = (0..1001)
+                .map(|_| serde_json::json!({ "postcode": "2222AA" }))
+                .collect::<Vec<serde_json::Value>>();
+
+            let bodies = [
+                serde_json::json!({ "postcode": "2222AA" }),
+                serde_json::json!([{ "number": "1" }]),
+                serde_json::Value::Array(too_large),
+            ];
+            for body in bodies.iter() {
+                let req = test::TestRequest::post()
+                    .uri("/addresses/batch")
+                    .set_json(body)
+                    .to_request();
+
+                let resp = app.call(req).await.unwrap();
+                assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+                let body = test::read_body(resp).await;
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                assert_eq!(body["code"], "invalid_body");
+            }
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_verify_address() {
+        run_test(async {
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            create_amsterdam_test_set().await;
+            web::block(|| {
+                import_addresses(
+                    &POOL.get().unwrap(),
+                    "NL",
+                    &[
+                        AddressRecord {
+                            lat: 52.3650,
+                            lon: 4.8900,
+                            number: "20".to_string(),
+                            street: "Kerkstraat".to_string(),
+                            city: "Amsterdam".to_string(),
+                            region: "Noord-Holland".to_string(),
+                            postcode: "1017GL".to_string()
+                        },
+                    ]
+                )
+            })
+            .await
+            .expect("Error creating tests data");
+
+            let verify = |body: serde_json::Value| {
+                test::TestRequest::post()
+                    .uri("/verify")
+                    .set_json(&body)
+                    .to_request()
+            };
+
+            let req = verify(serde_json::json!({
+                "street": "amstel", "number": "1", "postcode": "1011 pn", "city": "Amsterdam"
+            }));
+            let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp["verdict"], "exact");
+            assert_eq!(resp["confidence"], 1.0);
+            assert_eq!(resp["differences"].as_array().unwrap().len(), 0);
+            assert_eq!(resp["address"]["postcode"], "1011PN");
+
+            let req = verify(serde_json::json!({
+                "street": "Amstl", "number": "1", "postcode": "1011PN", "city": "Amsterdam"
+            }));
+            let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp["verdict"], "corrected");
+            assert_eq!(resp["differences"][0]["field"], "street");
+            assert_eq!(resp["differences"][0]["submitted"], "Amstl");
+            assert_eq!(resp["differences"][0]["canonical"], "Amstel");
+            assert_eq!(resp["address"]["street"], "Amstel");
+
+            // Fields that weren't submitted aren't differences
+            let req = verify(serde_json::json!({ "number": "1", "postcode": "1011PN" }));
+            let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp["verdict"], "exact");
+            assert_eq!(resp["differences"].as_array().unwrap().len(), 0);
+            assert_eq!(resp["address"]["street"], "Amstel");
+
+            // Wrong postcode, found through the street and city
+            let req = verify(serde_json::json!({
+                "street": "Amstel", "number": "10", "postcode": "1011PN", "city": "Amsterdam"
+            }));
+            let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp["verdict"], "corrected");
+            assert_eq!(resp["differences"][0]["field"], "postcode");
+            assert_eq!(resp["address"]["postcode"], "1017AA");
+
+            // Wrong postcode and typos in the street and city, found through the postcode area
+            let req = verify(serde_json::json!({
+                "street": "Amstl", "number": "10", "postcode": "1017AB", "city": "Amsterdm"
+            }));
+            let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp["verdict"], "corrected");
+            assert_eq!(resp["address"]["street"], "Amstel");
+            assert_eq!(resp["address"]["postcode"], "1017AA");
+
+            let req = verify(serde_json::json!({ "street": "Kerkstraat", "number": "20" }));
+            let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp["verdict"], "ambiguous");
+            assert!(resp["address"].is_null());
+            assert_eq!(resp["candidates"].as_array().unwrap().len(), 2);
+
+            let req = verify(serde_json::json!({
+                "street": "Nowhere", "number": "5", "postcode": "9999ZZ", "city": "Utopia"
+            }));
+            let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp["verdict"], "not_found");
+            assert!(resp["address"].is_null());
+
+            for body in &[
+                serde_json::json!({ "street": "Amstel", "number": " " }),
+                serde_json::json!({ "number": "1" }),
+                serde_json::json!({ "street": "Amstel" }),
+            ] {
+                let resp = app.call(verify(body.clone())).await.unwrap();
+                assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+                let body = test::read_body(resp).await;
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                assert_eq!(body["code"], "invalid_body");
+            }
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_get_addresses_pagination() {
+        run_test(async {
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            create_test_set().await;
+            // Sorted by house number, not as text
+            web::block(|| {
+                let records = [
+                    AddressRecord {
+                        lat: 6.0,
+                        lon: 5.0,
+                        number: "10".to_string(),
+                        street: "Street".to_string(),
+                        city: "City".to_string(),
+                        region: "Region".to_string(),
+                        postcode: "2222AA".to_string()
+                    },
+                ];
+                import_addresses(&POOL.get().unwrap(), "NL", &records)
+            })
+            .await
+            .expect("Error creating tests data");
+
+            let mut numbers = vec![];
+            let mut uri = "/addresses?postcode=2222AA&limit=3".to_string();
+            loop {
+                let req = test::TestRequest::get().uri(&uri).to_request();
+                let resp = app.call(req).await.unwrap();
+                assert_eq!(resp.status(), StatusCode::OK);
+
+                let has_more = resp.headers().get("x-has-more").unwrap().to_str().unwrap().to_owned();
+                let cursor = resp
+                    .headers()
+                    .get("x-next-cursor")
+                    .map(|c| c.to_str().unwrap().to_owned());
+                let body = test::read_body(resp).await;
+                let page: Vec<Address> = serde_json::from_slice(&body).unwrap();
+                numbers.extend(page.into_iter().map(|a| a.number));
+
+                match cursor {
+                    Some(cursor) => {
+                        assert_eq!(has_more, "true");
+                        uri = format!("/addresses?postcode=2222AA&limit=3&cursor={}", cursor);
+                    },
+                    None => {
+                        assert_eq!(has_more, "false");
+                        break;
+                    },
+                }
+            }
+            assert_eq!(numbers, vec!["1", "2", "2A", "2B", "10"]);
+
+            let req = test::TestRequest::post()
+                .uri("/addresses/batch")
+                .set_json(&serde_json::json!([{ "postcode": "2222AA", "limit": 2 }]))
+                .to_request();
+
+            let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp[0]["addresses"].as_array().unwrap().len(), 2);
+            let cursor = resp[0]["next_cursor"].as_str().unwrap().to_owned();
+
+            // Cursors can be used with either endpoint
+            let req = test::TestRequest::get()
+                .uri(&format!("/addresses?postcode=2222AA&limit=2&cursor={}", cursor))
+                .to_request();
+
+            let resp: Vec<Address> = test::read_response_json(&mut app, req).await;
+            let numbers = resp.into_iter().map(|a| a.number).collect::<Vec<String>>();
+            assert_eq!(numbers, vec!["2A", "2B"]);
+
+            let req = test::TestRequest::post()
+                .uri("/addresses/batch")
+                .set_json(&serde_json::json!([{ "postcode": "2222AA", "limit": 2, "cursor": cursor }]))
+                .to_request();
+
+            let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp[0]["addresses"][0]["number"], "2A");
+            assert_eq!(resp[0]["addresses"][1]["number"], "2B");
+            let cursor = resp[0]["next_cursor"].as_str().unwrap().to_owned();
+
+            let req = test::TestRequest::post()
+                .uri("/addresses/batch")
+                .set_json(&serde_json::json!([{ "postcode": "2222AA", "limit": 2, "cursor": cursor }]))
+                .to_request();
+
+            let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp[0]["addresses"].as_array().unwrap().len(), 1);
+            assert_eq!(resp[0]["addresses"][0]["number"], "10");
+            assert!(resp[0]["next_cursor"].is_null());
+
+            for query in &["limit=0", "limit=201", "cursor=zz"] {
+                let req = test::TestRequest::get()
+                    .uri(&format!("/addresses?postcode=2222AA&{}", query))
+                    .to_request();
+
+                let resp = app.call(req).await.unwrap();
+                assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            }
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_import_removes_disappeared_addresses() {
+        run_test(async {
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            create_be_test_set().await;
+
+            let record = |number: &str, street: &str| AddressRecord {
+                lat: 52.0,
+                lon: 5.0,
+                number: number.to_string(),
+                street: street.to_string(),
+                city: "City".to_string(),
+                region: "Region".to_string(),
+                postcode: "4444CC".to_string()
+            };
+            let first_import = vec![record("1", "Street"), record("2", "Street"), record("3", "Street")];
+            let second_import = vec![record("2", "Renamed street"), record("3", "Street"), record("4", "Street")];
+
+            let counts = web::block(move || {
+                import_source_addresses(&POOL.get().unwrap(), "nl/countrywide", "NL", &first_import)
+            })
+            .await
+            .expect("Error importing addresses");
+            assert_eq!(counts, ImportCounts { inserted: 3, updated: 0, removed: 0 });
+
+            let counts = web::block(move || {
+                import_source_addresses(&POOL.get().unwrap(), "nl/countrywide", "NL", &second_import)
+            })
+            .await
+            .expect("Error importing addresses");
+            assert_eq!(counts, ImportCounts { inserted: 1, updated: 1, removed: 1 });
+
+            let req = test::TestRequest::get()
+                .uri("/addresses?postcode=4444CC")
+                .to_request();
+
+            let resp: Vec<Address> = test::read_response_json(&mut app, req).await;
+            let numbers = resp.iter().map(|a| a.number.as_str()).collect::<Vec<&str>>();
+            assert_eq!(numbers, vec!["2", "3", "4"]);
+            assert_eq!(resp[0].street, "Renamed street");
+
+            // Addresses of other sources are kept
+            let req = test::TestRequest::get()
+                .uri("/addresses?postcode=1000&country=BE")
+                .to_request();
+
+            let resp: Vec<Address> = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.len(), 1);
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_import_overlapping_sources() {
+        run_test(async {
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            let record = |number: &str, street: &str| AddressRecord {
+                lat: 52.0,
+                lon: 5.0,
+                number: number.to_string(),
+                street: street.to_string(),
+                city: "City".to_string(),
+                region: "Region".to_string(),
+                postcode: "4444CC".to_string()
+            };
+            let import = |source: &'static str, records: Vec<AddressRecord>| web::block(move || {
+                import_source_addresses(&POOL.get().unwrap(), source, "NL", &records)
+            });
+            let addresses = || test::TestRequest::get()
+                .uri("/addresses?postcode=4444CC")
+                .to_request();
+
+            let counts = import("nl/countrywide", vec![record("1", "Street"), record("2", "Street")]).await.unwrap();
+            assert_eq!(counts, ImportCounts { inserted: 2, updated: 0, removed: 0 });
+
+            // The shared address stays with the source that imported it first
+            let counts = import("nl/city", vec![record("2", "Other street"), record("3", "Street")]).await.unwrap();
+            assert_eq!(counts, ImportCounts { inserted: 1, updated: 0, removed: 0 });
+            let counts = import("nl/city", vec![record("2", "Other street"), record("3", "Street")]).await.unwrap();
+            assert_eq!(counts, ImportCounts { inserted: 0, updated: 0, removed: 0 });
+            let resp: Vec<Address> = test::read_response_json(&mut app, addresses()).await;
+            assert_eq!(resp.iter().map(|a| a.number.as_str()).collect::<Vec<_>>(), vec!["1", "2", "3"]);
+            assert_eq!(resp[1].street, "Street");
+
+            // Only the source it belongs to removes it
+            let counts = import("nl/city", vec![record("3", "Street")]).await.unwrap();
+            assert_eq!(counts, ImportCounts { inserted: 0, updated: 0, removed: 0 });
+            let resp: Vec<Address> = test::read_response_json(&mut app, addresses()).await;
+            assert_eq!(resp.iter().map(|a| a.number.as_str()).collect::<Vec<_>>(), vec!["1", "2", "3"]);
+
+            let counts = import("nl/countrywide", vec![record("1", "Renamed street")]).await.unwrap();
+            assert_eq!(counts, ImportCounts { inserted: 0, updated: 1, removed: 1 });
+            let resp: Vec<Address> = test::read_response_json(&mut app, addresses()).await;
+            assert_eq!(resp.iter().map(|a| a.number.as_str()).collect::<Vec<_>>(), vec!["1", "3"]);
+            assert_eq!(resp[0].street, "Renamed street");
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_import_duplicates_and_special_characters() {
+        run_test(async {
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            let record = |street: &str, lat: f32| AddressRecord {
+                lat,
+                lon: 5.0,
+                number: "1".to_string(),
+                street: street.to_string(),
+                city: "City".to_string(),
+                region: "".to_string(),
+                postcode: "5555DD".to_string()
+            };
+            let records = vec![record("Back\\slash\tTab", 52.0), record("Back\\slash\tTab", 52.0001)];
+            let counts = web::block(move || import_addresses(&POOL.get().unwrap(), "NL", &records))
+                .await
+                .expect("Error importing addresses");
+            assert_eq!(counts.inserted, 1);
+
+            let req = test::TestRequest::get()
+                .uri("/addresses?postcode=5555DD")
+                .to_request();
+
+            let resp: Vec<Address> = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.len(), 1);
+            assert_eq!(resp[0].street, "Back\\slash\tTab");
+            assert_eq!(resp[0].region, "");
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_get_changes() {
+        run_test(async {
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            let record = |number: &str, street: &str, lat: f32| AddressRecord {
+                lat,
+                lon: 5.0,
+                number: number.to_string(),
+                street: street.to_string(),
+                city: "City".to_string(),
+                region: "Region".to_string(),
+                postcode: "4444CC".to_string()
+            };
+            let imports = vec![
+                ("2", vec![record("1", "Street", 52.0), record("2", "Street", 52.0), record("3", "Street", 52.0)]),
+                ("3", vec![record("2", "Renamed street", 52.0), record("3", "Street", 52.1), record("4", "Street", 52.0)]),
+            ];
+            web::block(move || -> Result<(), RefreshError> {
+                let conn = POOL.get().unwrap();
+                for (version, records) in imports {
+                    stage_addresses("nl/countrywide", "NL", &records)?;
+                    publish_state(&conn, &StateInfo {
+                        source: "nl/countrywide".to_string(),
+                        url: "http://localhost/nl.zip".to_string(),
+                        hash: "hash".to_string(),
+                        version: version.to_string(),
+                        address_count: records.len()
+                    })?;
+                }
+                Ok(())
+            })
+            .await
+            .expect("Error importing addresses");
+
+            let req = test::TestRequest::get()
+                .uri("/changes?since=2")
+                .to_request();
+
+            let resp: Vec<AddressChange> = test::read_response_json(&mut app, req).await;
+            let mut changes = resp
+                .iter()
+                .map(|c| (c.change.as_str(), c.number.as_str()))
+                .collect::<Vec<(&str, &str)>>();
+            changes.sort();
+            assert_eq!(changes, vec![("added", "4"), ("moved", "3"), ("removed", "1"), ("renamed", "2")]);
+            assert!(resp.iter().all(|c| c.version == "3"));
+            let renamed = resp.iter().find(|c| c.change == "renamed").unwrap();
+            assert_eq!(renamed.previous_street.as_deref(), Some("Street"));
+            assert_eq!(renamed.street.as_deref(), Some("Renamed street"));
+            let removed = resp.iter().find(|c| c.change == "removed").unwrap();
+            assert_eq!(removed.street, None);
+
+            // Changes of version 2 are included as well, 3 addresses were added
+            let req = test::TestRequest::get()
+                .uri("/changes?since=1&limit=5")
+                .to_request();
+
+            let resp = app.call(req).await.unwrap();
+            assert_eq!(resp.headers().get("x-has-more").unwrap(), "true");
+            let cursor = resp.headers().get("x-next-cursor").unwrap().to_str().unwrap().to_owned();
+            let body = test::read_body(resp).await;
+            let page: Vec<AddressChange> = serde_json::from_slice(&body).unwrap();
+            assert_eq!(page.len(), 5);
+
+            let req = test::TestRequest::get()
+                .uri(&format!("/changes?since=1&limit=5&cursor={}", cursor))
+                .to_request();
+
+            let resp: Vec<AddressChange> = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.len(), 2);
+
+            for query in &["since=4", "since=1&limit=0", "since=1&cursor=abc", "since=1&country=XX"] {
+                let req = test::TestRequest::get()
+                    .uri(&format!("/changes?{}", query))
+                    .to_request();
+
+                let resp = app.call(req).await.unwrap();
+                assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            }
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_refresh_from_local_data() {
+        run_test(async {
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            let directory = tempfile::tempdir().unwrap();
+            let csv_data = "LON,LAT,NUMBER,STREET,UNIT,CITY,DISTRICT,REGION,POSTCODE,ID,HASH\n\
+                4.9,52.37,1,Street,,City,,Region,6666EE,,a\n\
+                4.9,52.37,2,Street,,City,,Region,6666EE,,b\n";
+
+            // Bare csv file
+            let csv_path = directory.path().join("addresses.csv");
+            std::fs::write(&csv_path, csv_data).unwrap();
+            test_refresh(&test_refresh_config(DataLocation::File(csv_path)))
+                .await
+                .expect("Error importing csv file");
+
+            let req = test::TestRequest::get()
+                .uri("/addresses?postcode=6666EE")
+                .to_request();
+
+            let resp: Vec<Address> = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.len(), 2);
+
+            // Mirror directory, with a zip file listed in state.txt
+            let zip_path = directory.path().join("runs/1/nl/countrywide.zip");
+            let zip_data = test_data_zip(&csv_data.lines().take(2).collect::<Vec<&str>>().join("\n"));
+            std::fs::create_dir_all(zip_path.parent().unwrap()).unwrap();
+            std::fs::write(&zip_path, &zip_data).unwrap();
+            std::fs::write(directory.path().join("state.txt"), test_state_info(&zip_data, "2")).unwrap();
+            let mirror = DataLocation::Mirror(directory.path().to_owned());
+            test_refresh(&test_refresh_config(mirror.clone()))
+                .await
+                .expect("Error importing mirror");
+
+            let req = test::TestRequest::get()
+                .uri("/addresses?postcode=6666EE")
+                .to_request();
+
+            let resp: Vec<Address> = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.len(), 1);
+            assert_eq!(resp[0].number, "1");
+
+            // The same version is only imported again when forced
+            let imported_state = current_state(&POOL.get().unwrap(), "nl/countrywide").unwrap().unwrap();
+            test_refresh(&test_refresh_config(mirror.clone())).await.unwrap();
+            let state = current_state(&POOL.get().unwrap(), "nl/countrywide").unwrap().unwrap();
+            assert_eq!(state.id, imported_state.id);
+
+            test_refresh(&RefreshConfig { force: true, ..test_refresh_config(mirror) })
+                .await
+                .expect("Error importing mirror again");
+            let state = current_state(&POOL.get().unwrap(), "nl/countrywide").unwrap().unwrap();
+            assert_ne!(state.id, imported_state.id);
+            assert_eq!(state.version, imported_state.version);
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_refresh_from_remote_mirror() {
+        run_test(async {
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            // Stand-in for OpenAddresses, data files are served under /mirror
+            let zip_data = test_data_zip(
+                "LON,LAT,NUMBER,STREET,UNIT,CITY,DISTRICT,REGION,POSTCODE,ID,HASH\n\
+                4.9,52.37,1,Street,,City,,Region,7777FF,,a\n"
+            );
+            let state_info = test_state_info(&zip_data, "2");
+            let server = test::start(move || {
+                let zip_data = zip_data.clone();
+                let state_info = state_info.clone();
+                App::new()
+                    .route(
+                        "/state.txt",
+                        web::get().to(move || HttpResponse::Ok().body(state_info.clone()))
+                    )
+                    .route(
+                        "/mirror/runs/1/nl/countrywide.zip",
+                        web::get().to(move || HttpResponse::Ok().body(zip_data.clone()))
+                    )
+            });
+
+            let location = DataLocation::Remote(RemoteConfig {
+                state_info_url: server.url("/state.txt"),
+                download_base_url: Some(server.url("/mirror")),
+                user_agent: "postcode-service-tests".to_string(),
+                ..RemoteConfig::default()
+            });
+            test_refresh(&test_refresh_config(location))
+                .await
+                .expect("Error importing from mirror");
+
+            let req = test::TestRequest::get()
+                .uri("/addresses?postcode=7777FF")
+                .to_request();
+
+            let resp: Vec<Address> = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.len(), 1);
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_refresh_rejects_hash_mismatch() {
+        run_test(async {
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            // state.txt advertises the hash of other data
+            let zip_data = test_data_zip(
+                "LON,LAT,NUMBER,STREET,UNIT,CITY,DISTRICT,REGION,POSTCODE,ID,HASH\n\
+                4.9,52.37,1,Street,,City,,Region,7777FF,,a\n"
+            );
+            let state_info = test_state_info(b"other data", "2");
+            let server_zip_data = zip_data.clone();
+            let server = test::start(move || {
+                let zip_data = server_zip_data.clone();
+                let state_info = state_info.clone();
+                App::new()
+                    .route(
+                        "/state.txt",
+                        web::get().to(move || HttpResponse::Ok().body(state_info.clone()))
+                    )
+                    .route(
+                        "/mirror/runs/1/nl/countrywide.zip",
+                        web::get().to(move || HttpResponse::Ok().body(zip_data.clone()))
+                    )
+            });
+
+            let previous_state = current_state(&POOL.get().unwrap(), "nl/countrywide").unwrap();
+            let download_dir = tempfile::tempdir().unwrap();
+            let location = DataLocation::Remote(RemoteConfig {
+                state_info_url: server.url("/state.txt"),
+                download_base_url: Some(server.url("/mirror")),
+                download_dir: Some(download_dir.path().to_owned()),
+                user_agent: "postcode-service-tests".to_string(),
+                ..RemoteConfig::default()
+            });
+            match test_refresh(&test_refresh_config(location)).await {
+                Err(RefreshError::HashMismatch { actual, .. }) => {
+                    assert_eq!(actual, format!("{:x}", md5::compute(&zip_data)))
+                },
+                other => panic!("Unexpected result {:?}", other),
+            }
+
+            // Nothing is imported, and the download is discarded
+            let state = current_state(&POOL.get().unwrap(), "nl/countrywide").unwrap();
+            assert_eq!(state.map(|s| s.id), previous_state.as_ref().map(|s| s.id));
+            assert_eq!(std::fs::read_dir(download_dir.path()).unwrap().count(), 0);
+
+            let req = test::TestRequest::get()
+                .uri("/addresses?postcode=7777FF")
+                .to_request();
+
+            let resp: Vec<Address> = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.len(), 0);
+
+            // Files of a mirror directory are verified as well
+            let directory = tempfile::tempdir().unwrap();
+            let zip_path = directory.path().join("runs/1/nl/countrywide.zip");
+            std::fs::create_dir_all(zip_path.parent().unwrap()).unwrap();
+            std::fs::write(&zip_path, &zip_data).unwrap();
+            std::fs::write(directory.path().join("state.txt"), test_state_info(b"other data", "2")).unwrap();
+            let mirror = DataLocation::Mirror(directory.path().to_owned());
+            match test_refresh(&test_refresh_config(mirror)).await {
+                Err(RefreshError::HashMismatch { .. }) => {},
+                other => panic!("Unexpected result {:?}", other),
+            }
+            let state = current_state(&POOL.get().unwrap(), "nl/countrywide").unwrap();
+            assert_eq!(state.map(|s| s.id), previous_state.map(|s| s.id));
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_refresh_waits_for_other_import() {
+        run_test(async {
+            let directory = tempfile::tempdir().unwrap();
+            let csv_path = directory.path().join("addresses.csv");
+            std::fs::write(
+                &csv_path,
+                "LON,LAT,NUMBER,STREET,UNIT,CITY,DISTRICT,REGION,POSTCODE,ID,HASH\n\
+                4.9,52.37,1,Street,,City,,Region,7777FF,,a\n"
+            ).unwrap();
+            let config = test_refresh_config(DataLocation::File(csv_path));
+            let previous_state = current_state(&POOL.get().unwrap(), "nl/countrywide").unwrap();
+
+            // Another instance is importing
+            let lock = ImportLock::try_acquire(POOL.get().unwrap()).unwrap().unwrap();
+            assert!(ImportLock::try_acquire(POOL.get().unwrap()).unwrap().is_none());
+            match test_refresh(&config).await {
+                Err(RefreshError::ImportInProgress) => {},
+                other => panic!("Unexpected result {:?}", other),
+            }
+            let state = current_state(&POOL.get().unwrap(), "nl/countrywide").unwrap();
+            assert_eq!(state.map(|s| s.id), previous_state.as_ref().map(|s| s.id));
+
+            // The source is imported once the lock is released
+            drop(lock);
+            test_refresh(&config).await.unwrap();
+            let state = current_state(&POOL.get().unwrap(), "nl/countrywide").unwrap();
+            assert_ne!(state.map(|s| s.id), previous_state.map(|s| s.id));
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_import_command_fails_during_other_import() {
+        run_test(async {
+            let directory = tempfile::tempdir().unwrap();
+            let csv_path = directory.path().join("addresses.csv");
+            std::fs::write(
+                &csv_path,
+                "LON,LAT,NUMBER,STREET,UNIT,CITY,DISTRICT,REGION,POSTCODE,ID,HASH\n\
+                4.9,52.37,1,Street,,City,,Region,7777FF,,a\n"
+            ).unwrap();
+            let config = Config {
+                database: DatabaseConfig { url: test_database_url(), pool_size: 2, insecure_tls: false },
+                data: DataConfig { sources: vec!["nl/countrywide".to_owned()], ..DataConfig::default() },
+                ..Config::default()
+            };
+            let import = || Command::Import { source: Some(csv_path.to_str().unwrap().to_owned()), force: false };
+            let previous_state = current_state(&POOL.get().unwrap(), "nl/countrywide").unwrap();
+
+            // The refresher of a serving instance is importing
+            let lock = ImportLock::try_acquire(POOL.get().unwrap()).unwrap().unwrap();
+            match run(import(), config.clone()).await {
+                Err(CommandError::Refresh(RefreshError::ImportInProgress)) => {},
+                other => panic!("Unexpected result {:?}", other),
+            }
+            let state = current_state(&POOL.get().unwrap(), "nl/countrywide").unwrap();
+            assert_eq!(state.map(|s| s.id), previous_state.as_ref().map(|s| s.id));
+
+            drop(lock);
+            run(import(), config).await.expect("Error importing");
+            let state = current_state(&POOL.get().unwrap(), "nl/countrywide").unwrap();
+            assert_ne!(state.map(|s| s.id), previous_state.map(|s| s.id));
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_refresh_rejects_incomplete_data() {
+        run_test(async {
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            let directory = tempfile::tempdir().unwrap();
+            let zip_path = directory.path().join("runs/1/nl/countrywide.zip");
+            std::fs::create_dir_all(zip_path.parent().unwrap()).unwrap();
+            let import = |csv_data: &str, version: &str, address_count: usize| {
+                let zip_data = test_data_zip(csv_data);
+                std::fs::write(&zip_path, &zip_data).unwrap();
+                std::fs::write(
+                    directory.path().join("state.txt"),
+                    test_state_info_with_count(&zip_data, version, address_count)
+                ).unwrap();
+                test_refresh_config(DataLocation::Mirror(directory.path().to_owned()))
+            };
+
+            let config = import(
+                "LON,LAT,NUMBER,STREET,UNIT,CITY,DISTRICT,REGION,POSTCODE,ID,HASH\n\
+                4.9,52.37,1,Street,,City,,Region,7777FF,,a\n",
+                "2",
+                1
+            );
+            test_refresh(&config).await.unwrap();
+            let previous_state = current_state(&POOL.get().unwrap(), "nl/countrywide").unwrap();
+
+            // The data file has far less addresses than advertised
+            let config = import(
+                "LON,LAT,NUMBER,STREET,UNIT,CITY,DISTRICT,REGION,POSTCODE,ID,HASH\n\
+                4.9,52.37,1,Street,,City,,Region,7777FF,,a\n\
+                4.9,52.37,2,Street,,City,,Region,7777FF,,b\n",
+                "3",
+                10
+            );
+            match test_refresh(&config).await {
+                Err(RefreshError::InvalidData(_)) => {},
+                other => panic!("Unexpected result {:?}", other),
+            }
+
+            // The live data is left unchanged
+            let state = current_state(&POOL.get().unwrap(), "nl/countrywide").unwrap();
+            assert_eq!(state.map(|s| s.id), previous_state.map(|s| s.id));
+            assert_eq!(count_staged_addresses(&POOL.get().unwrap(), "nl/countrywide").unwrap(), 0);
+
+            let req = test::TestRequest::get()
+                .uri("/addresses?postcode=7777FF")
+                .to_request();
+
+            let resp: Vec<Address> = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.iter().map(|a| a.number.as_str()).collect::<Vec<_>>(), vec!["1"]);
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_staged_data_published_at_once() {
+        run_test(async {
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            let record = |number: &str| AddressRecord {
+                lat: 52.0,
+                lon: 5.0,
+                number: number.to_string(),
+                street: "Street".to_string(),
+                city: "City".to_string(),
+                region: "Region".to_string(),
+                postcode: "4444CC".to_string()
+            };
+            let publish = |records: Vec<AddressRecord>, version: &str| {
+                let version = version.to_string();
+                web::block(move || -> Result<(), RefreshError> {
+                    publish_state(&POOL.get().unwrap(), &StateInfo {
+                        source: "nl/countrywide".to_string(),
+                        url: "http://localhost/nl.zip".to_string(),
+                        hash: "hash".to_string(),
+                        version,
+                        address_count: records.len()
+                    })?;
+                    Ok(())
+                })
+            };
+            let numbers = |resp: Vec<Address>| resp.into_iter().map(|a| a.number).collect::<Vec<_>>();
+
+            let stage = |records: Vec<AddressRecord>| web::block(move || {
+                clear_staged_addresses(&POOL.get().unwrap(), "nl/countrywide")?;
+                stage_addresses("nl/countrywide", "NL", &records)
+            });
+
+            stage(vec![record("1"), record("2")]).await.unwrap();
+            publish(vec![record("1"), record("2")], "2").await.unwrap();
+
+            // Staged addresses of the next version aren't served yet
+            stage(vec![record("2"), record("3")]).await.unwrap();
+            let req = test::TestRequest::get()
+                .uri("/addresses?postcode=4444CC")
+                .to_request();
+            let resp: Vec<Address> = test::read_response_json(&mut app, req).await;
+            assert_eq!(numbers(resp), vec!["1", "2"]);
+
+            publish(vec![record("2"), record("3")], "3").await.unwrap();
+            let req = test::TestRequest::get()
+                .uri("/addresses?postcode=4444CC")
+                .to_request();
+            let resp: Vec<Address> = test::read_response_json(&mut app, req).await;
+            assert_eq!(numbers(resp), vec!["2", "3"]);
+
+            // Region counts are saved along with the new state
+            let counts = web::block(|| count_addresses_by_region(&POOL.get().unwrap())).await.unwrap();
+            assert_eq!(counts.len(), 1);
+            assert_eq!((counts[0].region.as_str(), counts[0].count), ("Region", 2));
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_download_to_temporary_file() {
+        let data = test_data_zip(
+            "LON,LAT,NUMBER,STREET,UNIT,CITY,DISTRICT,REGION,POSTCODE,ID,HASH\n\
+            4.9,52.37,1,Street,,City,,Region,7777FF,,a\n"
+        );
+        let hash = format!("{:x}", md5::compute(&data));
+        let server_data = data.clone();
+        let server = test::start(move || {
+            let data = server_data.clone();
+            App::new().route("/data.zip", web::get().to(move || HttpResponse::Ok().body(data.clone())))
+        });
+
+        let directory = tempfile::tempdir().unwrap();
+        let client = RemoteConfig::default().client().unwrap();
+        let file = download_to_file(
+            &client,
+            &server.url("/data.zip"),
+            &hash,
+            directory.path(),
+            &RetryPolicy::default(),
+            &Shutdown::default()
+        )
+        .await
+        .expect("Error downloading data file");
+
+        // Written to the download directory, and removed once dropped
+        assert_eq!(file.path().parent(), Some(directory.path()));
+        assert_eq!(std::fs::read(file.path()).unwrap(), data);
+        drop(file);
+        assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 0);
+    }
+
+    #[actix_rt::test]
+    async fn test_refresh_retries_and_resumes_download() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        run_test(async {
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+
+            // Stand-in for OpenAddresses that fails the first state info request,
+            // interrupts the first download halfway, and fails the first range request
+            let zip_data = test_data_zip(
+                "LON,LAT,NUMBER,STREET,UNIT,CITY,DISTRICT,REGION,POSTCODE,ID,HASH\n\
+                4.9,52.37,1,Street,,City,,Region,8888GG,,a\n"
+            );
+            let state_info = test_state_info(&zip_data, "3");
+            let state_requests = Arc::new(AtomicUsize::new(0));
+            let ranges = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let server_state_requests = state_requests.clone();
+            let server_ranges = ranges.clone();
+            let server = test::start(move || {
+                let zip_data = zip_data.clone();
+                let state_info = state_info.clone();
+                let state_requests = server_state_requests.clone();
+                let ranges = server_ranges.clone();
+                App::new()
+                    .route(
+                        "/state.txt",
+                        web::get().to(move || {
+                            if state_requests.fetch_add(1, Ordering::SeqCst) == 0 {
+                                HttpResponse::ServiceUnavailable().finish()
+                            } else {
+                                HttpResponse::Ok().body(state_info.clone())
+                            }
+                        })
+                    )
+                    .route(
+                        "/mirror/runs/1/nl/countrywide.zip",
+                        web::get().to(move |req: actix_web::HttpRequest| {
+                            let range = req
+                                .headers()
+                                .get("range")
+                                .and_then(|value| value.to_str().ok())
+                                .map(str::to_owned);
+                            let range_requests = {
+                                let mut ranges = ranges.lock().unwrap();
+                                ranges.push(range.clone());
+                                ranges.iter().filter(|range| range.is_some()).count()
+                            };
+
+                            let half = zip_data.len() / 2;
+                            match range {
+                                None => {
+                                    // The first half is flushed before the connection drops
+                                    let first_half = bytes::Bytes::from(zip_data[..half].to_vec());
+                                    let interrupted = async {
+                                        actix_rt::time::delay_for(std::time::Duration::from_millis(50)).await;
+                                        Err(actix_web::error::ErrorInternalServerError("interrupted"))
+                                    };
+                                    HttpResponse::Ok().streaming(Box::pin(
+                                        futures::stream::once(async { Ok(first_half) })
+                                            .chain(futures::stream::once(interrupted))
+                                    ))
+                                },
+                                Some(_) if range_requests == 1 => HttpResponse::ServiceUnavailable().finish(),
+                                Some(_) => HttpResponse::PartialContent()
+                                    .header(
+                                        "content-range",
+                                        format!("bytes {}-{}/{}", half, zip_data.len() - 1, zip_data.len())
+                                    )
+                                    .body(zip_data[half..].to_vec()),
+                            }
+                        })
+                    )
+            });
+
+            let location = DataLocation::Remote(RemoteConfig {
+                state_info_url: server.url("/state.txt"),
+                download_base_url: Some(server.url("/mirror")),
+                user_agent: "postcode-service-tests".to_string(),
+                retry: RetryPolicy {
+                    max_attempts: 3,
+                    initial_delay: std::time::Duration::from_millis(10),
+                    max_delay: std::time::Duration::from_millis(50)
+                },
+                ..RemoteConfig::default()
+            });
+            test_refresh(&test_refresh_config(location))
+                .await
+                .expect("Error importing from mirror");
+
+            assert_eq!(state_requests.load(Ordering::SeqCst), 2);
+            // The data received before the server error is kept
+            let ranges = ranges.lock().unwrap().clone();
+            assert_eq!(ranges.len(), 3);
+            assert_eq!(ranges[0], None);
+            assert!(ranges[1].is_some());
+            assert_eq!(ranges[2], ranges[1]);
+
+            let req = test::TestRequest::get()
+                .uri("/addresses?postcode=8888GG")
+                .to_request();
+
+            let resp: Vec<Address> = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.len(), 1);
+        })
+        .await
+    }
+
+    #[test]
+    fn test_bulk_loader_ssl_modes() {
+        let url = |ssl_mode: &str| format!("{}?sslmode={}", test_database_url(), ssl_mode);
+
+        // libpq modes are mapped to the ones of the postgres crate
+        assert!(BulkLoader::connect(&url("allow"), false).is_ok());
+        assert!(BulkLoader::connect(&url("disable"), false).is_ok());
+        assert!(BulkLoader::connect(&url("unknown"), false).is_err());
+        // The test database doesn't use TLS
+        assert!(BulkLoader::connect(&url("verify-full"), false).is_err());
+        // Certificates are always verified when the sslmode requires it
+        assert!(BulkLoader::connect(&url("verify-ca"), true).is_err());
+    }
+
+    #[test]
+    fn test_error_codes() {
+        let codes = [
+            ErrorCode::InvalidQuery,
+            ErrorCode::InvalidBody,
+            ErrorCode::EmptyPostcode,
+            ErrorCode::InvalidPostcodeFormat,
+            ErrorCode::UnsupportedCountry,
+            ErrorCode::NotFound,
+            ErrorCode::Unauthorized,
+            ErrorCode::RefreshInProgress,
+            ErrorCode::DatabaseUnavailable,
+            ErrorCode::DataNotReady,
+            ErrorCode::RefresherUnavailable,
+            ErrorCode::InternalError,
+        ];
+        for code in codes.iter() {
+            assert_eq!(serde_json::to_value(code).unwrap(), code.as_str());
+        }
+
+        // Logged errors use the same code as the response
+        let error = ApiError::invalid_body("Missing queries");
+        assert_eq!(error.to_string(), "invalid_body: Missing queries");
+    }
+
+    #[test]
+    fn test_config_file_and_env_overrides() {
+        let mut config: Config = toml::from_str(r#"
+            [server]
+            bind_address = "127.0.0.1:8080"
+
+            [database]
+            url = "postgres://localhost/addresses"
+            pool_size = 4
+
+            [data]
+            sources = ["nl/countrywide", "be/countrywide"]
+            refresh_interval_secs = 3600
+
+            [data.remote]
+            retry = { max_attempts = 2 }
+        "#).unwrap();
+
+        let env: HashMap<&str, &str> = vec![
+            ("DATABASE_POOL_SIZE", "8"),
+            ("DATABASE_INSECURE_TLS", "true"),
+            ("DATA_SOURCES", "dk/countrywide, "),
+        ].into_iter().collect();
+        config.apply_env(|name| env.get(name).map(|value| value.to_string())).unwrap();
+
+        assert_eq!(config.server.bind_address, "127.0.0.1:8080");
+        assert_eq!(config.server.log_level, "info");
+        assert_eq!(config.database.pool_size, 8);
+        assert!(config.database.insecure_tls);
+        assert!(config.refresh_config().database_insecure_tls);
+        assert_eq!(config.api.addresses_max_limit, 200);
+        assert_eq!(config.data.sources, vec!["dk/countrywide"]);
+        assert_eq!(config.data.refresh_interval, Duration::from_secs(3600));
+        assert_eq!(config.data.remote.retry.max_attempts, 2);
+        assert!(config.validate().is_ok());
+
+        config.database.pool_size = 0;
+        match config.validate() {
+            Err(ConfigError::Invalid { field, .. }) => assert_eq!(field, "database.pool_size"),
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        let result = config.apply_env(|name| match name {
+            "DATA_REFRESH_INTERVAL_SECS" => Some("daily".to_string()),
+            _ => None,
+        });
+        match result {
+            Err(ConfigError::InvalidVar { name, .. }) => assert_eq!(name, "DATA_REFRESH_INTERVAL_SECS"),
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        let result: Result<Config, _> = toml::from_str("[server]\nport = 3000");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_command_line() {
+        let parse = |args: &[&str]| Cli::from_iter_safe(args).map(|cli| cli.command.unwrap_or_default());
+
+        assert_eq!(parse(&["postcode-service"]).unwrap(), Command::Serve { no_refresh: false });
+        assert_eq!(
+            parse(&["postcode-service", "import", "--source", "data.zip", "--force"]).unwrap(),
+            Command::Import { source: Some("data.zip".to_owned()), force: true }
+        );
+        assert_eq!(
+            parse(&["postcode-service", "lookup", "1011PN", "1A", "--country", "NL"]).unwrap(),
+            Command::Lookup {
+                postcode: "1011PN".to_owned(),
+                number: Some("1A".to_owned()),
+                country: Some("NL".to_owned())
+            }
+        );
+        assert!(parse(&["postcode-service", "lookup"]).is_err());
+        assert!(parse(&["postcode-service", "refresh"]).is_err());
+
+        let remote = RemoteConfig::default();
+        let directory = tempfile::tempdir().unwrap();
+        match import_location("https://mirror.example.com/state.txt", &remote).unwrap() {
+            DataLocation::Remote(config) => {
+                assert_eq!(config.state_info_url, "https://mirror.example.com/state.txt")
+            },
+            other => panic!("Unexpected location {:?}", other),
+        }
+        assert_eq!(
+            import_location(directory.path().to_str().unwrap(), &remote).unwrap(),
+            DataLocation::Mirror(directory.path().to_owned())
+        );
+        assert!(import_location("missing.zip", &remote).is_err());
+    }
+
+    #[test]
+    fn test_resolve_mirror_path() {
+        let root = tempfile::tempdir().unwrap();
+        let directory = root.path().join("mirror");
+        std::fs::create_dir_all(directory.join("runs/1/nl")).unwrap();
+        std::fs::write(directory.join("runs/1/nl/countrywide.zip"), "").unwrap();
+        std::fs::write(root.path().join("secret.zip"), "").unwrap();
+
+        let file = directory.join("runs/1/nl/countrywide.zip");
+        assert_eq!(resolve_mirror_path(&directory, "https://data.example.com/runs/1/nl/countrywide.zip"), Some(file));
+        assert_eq!(resolve_mirror_path(&directory, "countrywide.zip"), None);
+        std::fs::write(directory.join("countrywide.zip"), "").unwrap();
+        assert_eq!(resolve_mirror_path(&directory, "countrywide.zip"), Some(directory.join("countrywide.zip")));
+
+        // Urls never resolve to files outside the mirror directory
+        assert_eq!(resolve_mirror_path(&directory, "../secret.zip"), None);
+        assert_eq!(resolve_mirror_path(&directory, "https://data.example.com/../secret.zip"), None);
+        let absolute = root.path().join("secret.zip");
+        assert_eq!(resolve_mirror_path(&directory, absolute.to_str().unwrap()), None);
+    }
+
+    #[actix_rt::test]
+    async fn test_interrupted_import() {
+        run_test(async {
+            let directory = tempfile::tempdir().unwrap();
+            let csv_path = directory.path().join("addresses.csv");
+            std::fs::write(
+                &csv_path,
+                "LON,LAT,NUMBER,STREET,UNIT,CITY,DISTRICT,REGION,POSTCODE,ID,HASH\n\
+                4.9,52.37,1,Street,,City,,Region,6666EE,,a\n"
+            ).unwrap();
+
+            // Nothing is imported once the shutdown is requested
+            let previous_state = current_state(&POOL.get().unwrap(), "nl/countrywide").unwrap();
+            let shutdown = Shutdown::default();
+            shutdown.request();
+            let location = DataLocation::File(csv_path);
+            let monitor = RefreshMonitor::default();
+            let result = refresh_state(&POOL, &test_refresh_config(location), &monitor, &shutdown).await;
+            match result {
+                Err(RefreshError::Interrupted) => {},
+                other => panic!("Unexpected result {:?}", other),
+            }
+            let state = current_state(&POOL.get().unwrap(), "nl/countrywide").unwrap();
+            assert_eq!(state.map(|s| s.id), previous_state.map(|s| s.id));
+
+            // Left behind by an import killed before publishing
+            web::block(|| {
+                let records = [
+                    AddressRecord {
+                        lat: 52.37,
+                        lon: 4.9,
+                        number: "1".to_string(),
+                        street: "Street".to_string(),
+                        city: "City".to_string(),
+                        region: "Region".to_string(),
+                        postcode: "6666EE".to_string()
+                    },
+                ];
+                stage_addresses("nl/countrywide", "NL", &records)
+            })
+            .await
+            .unwrap();
+            let download_path = directory.path().join("postcode-data-interrupted.zip");
+            std::fs::write(&download_path, "partial").unwrap();
+            let other_path = directory.path().join("other.zip");
+            std::fs::write(&other_path, "other").unwrap();
+
+            let location = DataLocation::Remote(RemoteConfig {
+                download_dir: Some(directory.path().to_owned()),
+                ..RemoteConfig::default()
+            });
+            let config = test_refresh_config(location);
+
+            // Nothing is discarded while another import may be using it
+            let lock = ImportLock::try_acquire(POOL.get().unwrap()).unwrap().unwrap();
+            recover_interrupted_imports(&POOL, &config)
+                .await
+                .expect("Error recovering interrupted imports");
+            assert_eq!(count_staged_addresses(&POOL.get().unwrap(), "nl/countrywide").unwrap(), 1);
+            assert!(download_path.exists());
+            drop(lock);
+
+            recover_interrupted_imports(&POOL, &config)
+                .await
+                .expect("Error recovering interrupted imports");
+            assert_eq!(count_staged_addresses(&POOL.get().unwrap(), "nl/countrywide").unwrap(), 0);
+            assert!(!download_path.exists());
+            assert!(other_path.exists());
+
+            // The refresher stops waiting for the next refresh
+            let mut refresher = StateRefresher::new(&Config::default(), false, RefreshMonitor::default());
+            refresher.refresh = test_refresh_config(DataLocation::Mirror(directory.path().to_owned()));
+            let shutdown = Shutdown::default();
+            let stop = async {
+                actix_rt::time::delay_for(Duration::from_millis(50)).await;
+                shutdown.request();
+            };
+            futures::join!(refresher.start(&POOL, &shutdown), stop);
+        })
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_admin_api() {
+        run_test(async {
+            let monitor = RefreshMonitor::default();
+            let mut refresher = StateRefresher::new(&Config::default(), false, monitor.clone());
+            let control = refresher.control();
+            let api_config = ApiConfig { admin_token: Some("secret".to_owned()), ..ApiConfig::default() };
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .data(monitor.clone())
+                    .data(control.clone())
+                    .configure(configure(api_config))
+            )
+            .await;
+
+            let directory = tempfile::tempdir().unwrap();
+            let zip_path = directory.path().join("runs/1/nl/countrywide.zip");
+            let zip_data = test_data_zip(
+                "LON,LAT,NUMBER,STREET,UNIT,CITY,DISTRICT,REGION,POSTCODE,ID,HASH\n\
+                4.9,52.37,1,Street,,City,,Region,8888GG,,a"
+            );
+            std::fs::create_dir_all(zip_path.parent().unwrap()).unwrap();
+            std::fs::write(&zip_path, &zip_data).unwrap();
+            std::fs::write(directory.path().join("state.txt"), test_state_info(&zip_data, "2")).unwrap();
+            refresher.refresh = test_refresh_config(DataLocation::Mirror(directory.path().to_owned()));
+
+            let admin_request = |method: &str, uri: &str| {
+                let request = match method {
+                    "POST" => test::TestRequest::post(),
+                    _ => test::TestRequest::get(),
+                };
+                request
+                    .uri(uri)
+                    .header("Authorization", "Bearer secret")
+                    .to_request()
+            };
+            // Commands are handled by the refresher in the background,
+            // waits for the refresh following the given attempt to complete
+            let wait_for_refresh = |monitor: RefreshMonitor, previous_attempt_at| async move {
+                loop {
+                    let report = monitor.report();
+                    if report.last_attempt_at != previous_attempt_at && !report.refreshing {
+                        break;
+                    }
+                    actix_rt::time::delay_for(Duration::from_millis(10)).await;
+                }
+            };
+
+            let shutdown = Shutdown::default();
+            let admin = async {
+                // Missing or wrong token
+                let req = test::TestRequest::post().uri("/admin/refresh").to_request();
+                let resp = test::call_service(&mut app, req).await;
+                assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+                let req = test::TestRequest::post()
+                    .uri("/admin/refresh")
+                    .header("Authorization", "Bearer secrets")
+                    .to_request();
+                let resp = test::call_service(&mut app, req).await;
+                assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+                let resp = test::call_service(&mut app, admin_request("POST", "/admin/refresher/pause")).await;
+                assert_eq!(resp.status(), StatusCode::ACCEPTED);
+                actix_rt::time::delay_for(Duration::from_millis(50)).await;
+                let resp: serde_json::Value =
+                    test::read_response_json(&mut app, admin_request("GET", "/admin/refresh/current")).await;
+                assert_eq!(resp, serde_json::json!({ "refreshing": false, "paused": true }));
+
+                // Refreshes can still be requested while paused, once at a time
+                let last_attempt_at = monitor.report().last_attempt_at;
+                let resp = test::call_service(&mut app, admin_request("POST", "/admin/refresh")).await;
+                assert_eq!(resp.status(), StatusCode::ACCEPTED);
+                let resp = test::call_service(&mut app, admin_request("POST", "/admin/refresh")).await;
+                assert_eq!(resp.status(), StatusCode::CONFLICT);
+                wait_for_refresh(monitor.clone(), last_attempt_at).await;
+                let req = test::TestRequest::get()
+                    .uri("/addresses?postcode=8888GG")
+                    .to_request();
+                let resp: Vec<Address> = test::read_response_json(&mut app, req).await;
+                assert_eq!(resp.len(), 1);
+
+                // The same version is only imported again when forced
+                let imported_state = current_state(&POOL.get().unwrap(), "nl/countrywide").unwrap().unwrap();
+                let last_attempt_at = monitor.report().last_attempt_at;
+                let resp = test::call_service(&mut app, admin_request("POST", "/admin/refresh")).await;
+                assert_eq!(resp.status(), StatusCode::ACCEPTED);
+                wait_for_refresh(monitor.clone(), last_attempt_at).await;
+                let state = current_state(&POOL.get().unwrap(), "nl/countrywide").unwrap().unwrap();
+                assert_eq!(state.id, imported_state.id);
+
+                let last_attempt_at = monitor.report().last_attempt_at;
+                let resp = test::call_service(&mut app, admin_request("POST", "/admin/refresh?force=true")).await;
+                assert_eq!(resp.status(), StatusCode::ACCEPTED);
+                wait_for_refresh(monitor.clone(), last_attempt_at).await;
+                let state = current_state(&POOL.get().unwrap(), "nl/countrywide").unwrap().unwrap();
+                assert_ne!(state.id, imported_state.id);
+
+                let resp = test::call_service(&mut app, admin_request("POST", "/admin/refresher/resume")).await;
+                assert_eq!(resp.status(), StatusCode::ACCEPTED);
+                actix_rt::time::delay_for(Duration::from_millis(50)).await;
+                let report = monitor.report();
+                assert!(!report.paused);
+                assert!(report.next_refresh_at.is_some());
+
+                shutdown.request();
+            };
+            futures::join!(refresher.start(&POOL, &shutdown), admin);
+
+            // Once the refresher is stopped
+            let resp = test::call_service(&mut app, admin_request("POST", "/admin/refresh")).await;
+            assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+            let body = test::read_body(resp).await;
+            let resp: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(resp["code"], "refresher_unavailable");
+
+            // Admin endpoints are disabled without a token
+            let mut app = test::init_service(
+                App::new()
+                    .data(POOL.clone())
+                    .data(monitor.clone())
+                    .data(control.clone())
+                    .configure(configure(ApiConfig::default()))
+            )
+            .await;
+            let resp = test::call_service(&mut app, admin_request("POST", "/admin/refresh")).await;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        })
+        .await
+    }
+
+    async fn test_refresh(config: &RefreshConfig) -> Result<(), RefreshError> {
+        refresh_state(&POOL, config, &RefreshMonitor::default(), &Shutdown::default()).await
+    }
+
+    /// Refresh of the nl/countrywide source from the given location
+    fn test_refresh_config(location: DataLocation) -> RefreshConfig {
+        RefreshConfig {
+            sources: vec![Source::new("nl/countrywide")],
+            location,
+            database_url: test_database_url(),
+            database_insecure_tls: false,
+            force: false
+        }
+    }
+
+    /// Zip file containing the given data as the nl/countrywide source
+    fn test_data_zip(csv_data: &str) -> Vec<u8> {
+        use std::io::Write;
+
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        zip.start_file("nl/countrywide.csv", Default::default()).unwrap();
+        zip.write_all(csv_data.as_bytes()).unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    /// state.txt listing the given zip file as the nl/countrywide source
+    fn test_state_info(zip_data: &[u8], version: &str) -> String {
+        test_state_info_with_count(zip_data, version, 1)
+    }
+
+    /// state.txt listing the given zip file as the nl/countrywide source,
+    /// advertising the given address count
+    fn test_state_info_with_count(zip_data: &[u8], version: &str, address_count: usize) -> String {
+        let hash = format!("{:x}", md5::compute(zip_data));
+        let address_count = address_count.to_string();
+        let mut state_row = vec![""; 16];
+        state_row[0] = "nl/countrywide.json";
+        state_row[4] = &address_count;
+        state_row[8] = "http://data.openaddresses.io/runs/1/nl/countrywide.zip";
+        state_row[10] = &hash;
+        state_row[15] = version;
+
+        format!("{}\n{}\n", vec!["column"; 16].join("\t"), state_row.join("\t"))
+    }
+
+    /// Loads the records the same way as a data import, through the staging table.
+    /// Each call uses its own source, so that test sets don't replace each other.
+    fn import_addresses(
+        conn: &PgConnection,
+        country: &str,
+        records: &[AddressRecord]
+    ) -> Result<ImportCounts, RefreshError> {
+        let source = format!("test/{}", Uuid::new_v4());
+        import_source_addresses(conn, &source, country, records)
+    }
+
+    fn import_source_addresses(
+        conn: &PgConnection,
+        source: &str,
+        country: &str,
+        records: &[AddressRecord]
+    ) -> Result<ImportCounts, RefreshError> {
+        stage_addresses(source, country, records)?;
+        let counts = publish_staged_addresses(conn, source)?;
+        update_region_counts(conn, source)?;
+        clear_staged_addresses(conn, source)?;
+        Ok(counts)
+    }
+
+    fn stage_addresses(source: &str, country: &str, records: &[AddressRecord]) -> Result<(), RefreshError> {
+        BulkLoader::connect(&test_database_url(), false)?
+            .load(source, country, records.iter().cloned().map(Ok::<_, RefreshError>))?;
+        Ok(())
+    }
+
+    async fn create_amsterdam_test_set() {
+        web::block(|| {
+            import_addresses(
+                &POOL.get().unwrap(),
+                "NL",
+                &[
+                    AddressRecord {
+                        lat: 52.3676,
+                        lon: 4.9001,
+                        number: "1".to_string(),
+                        street: "Amstel".to_string(),
+                        city: "Amsterdam".to_string(),
+                        region: "Noord-Holland".to_string(),
+                        postcode: "1011PN".to_string()
+                    },
+                    AddressRecord {
+                        lat: 52.3660,
+                        lon: 4.9010,
+                        number: "10".to_string(),
+                        street: "Amstel".to_string(),
+                        city: "Amsterdam".to_string(),
+                        region: "Noord-Holland".to_string(),
+                        postcode: "1017AA".to_string()
+                    },
+                    AddressRecord {
+                        lat: 52.3500,
+                        lon: 4.8600,
+                        number: "1".to_string(),
+                        street: "Amstelveenseweg".to_string(),
+                        city: "Amsterdam".to_string(),
+                        region: "Noord-Holland".to_string(),
+                        postcode: "1075HT".to_string()
+                    },
+                    AddressRecord {
+                        lat: 52.3000,
+                        lon: 4.9200,
+                        number: "20".to_string(),
+                        street: "Kerkstraat".to_string(),
+                        city: "Ouderkerk aan de Amstel".to_string(),
+                        region: "Noord-Holland".to_string(),
+                        postcode: "1191JB".to_string()
+                    },
+                ]
+            )
+        })
+        .await
+        .expect("Error creating tests data");
+    }
+
+    async fn create_test_state() {
+        web::block(|| {
+            create_new_state(
+                &POOL.get().unwrap(),
+                &StateInfo {
+                    source: "nl/countrywide".to_string(),
+                    url: "http://localhost/nl.zip".to_string(),
+                    hash: "hash".to_string(),
+                    version: "1".to_string(),
+                    address_count: 4
+                }
+            )
+        })
+        .await
+        .expect("Error creating test state");
+    }
+
+    async fn create_be_test_set() {
+        web::block(|| {
+            import_addresses(
+                &POOL.get().unwrap(),
+                "BE",
+                &[
+                    AddressRecord {
+                        lat: 50.8,
+                        lon: 4.3,
+                        number: "1".to_string(),
+                        street: "Rue".to_string(),
+                        city: "Bruxelles".to_string(),
+                        region: "Bruxelles".to_string(),
+                        postcode: "1000".to_string()
+                    },
+                ]
+            )
+        })
+        .await
+        .expect("Error creating tests data");
+    }
+
     async fn create_test_set() {
         web::block(|| {
-            create_or_update_addresses(
+            import_addresses(
                 &POOL.get().unwrap(),
+                "NL",
                 &[
                     AddressRecord {
                         lat: 2.0,
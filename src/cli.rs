@@ -0,0 +1,333 @@
+use std::fmt::Formatter;
+use std::io;
+use std::path::PathBuf;
+
+use actix_web::{App, HttpServer, web};
+use actix_web::dev::Service;
+use actix_web::http::{HeaderName, HeaderValue};
+use actix_web::middleware::Logger;
+use actix_rt::signal;
+use actix_rt::signal::unix::SignalKind;
+use futures::future::{self, FutureExt};
+use futures::pin_mut;
+use log::info;
+use structopt::StructOpt;
+
+use crate::api;
+use crate::api::metrics::track_request;
+use crate::api::not_found;
+use crate::api::request_id::{REQUEST_ID_HEADER, RequestId};
+use crate::config::Config;
+use crate::data::house_number::parse_house_number;
+use crate::data::repo::addresses::{get_addresses, HouseNumberFilter, MatchMode};
+use crate::data::repo::states::{current_states, has_state};
+use crate::data::state::error::RefreshError;
+use crate::data::state::location::DataLocation;
+use crate::data::state::monitor::RefreshMonitor;
+use crate::data::state::{recover_interrupted_imports, refresh_state};
+use crate::data::state::remote::RemoteConfig;
+use crate::data::state::shutdown::Shutdown;
+use crate::data::state::state_refresher::StateRefresher;
+use crate::db::{init_connection_pool, Pool};
+use crate::postcode::{normalize_postcode, PostcodeError};
+
+/// Postcode to address API
+#[derive(Debug, StructOpt)]
+#[structopt(name = "postcode-service")]
+pub struct Cli {
+    /// Defaults to `serve`
+    #[structopt(subcommand)]
+    pub command: Option<Command>
+}
+
+#[derive(Debug, PartialEq, StructOpt)]
+pub enum Command {
+    /// Runs the migrations and serves the API, while refreshing the data in the background
+    Serve {
+        /// Only serve the data, when it is imported by a separate `import` job
+        #[structopt(long)]
+        no_refresh: bool
+    },
+    /// Runs the database migrations
+    Migrate,
+    /// Imports the data of the configured sources once
+    Import {
+        /// Data file, mirror directory or state index url to import from,
+        /// instead of the configured location
+        #[structopt(long)]
+        source: Option<String>,
+        /// Imports the data even when its version is the current one
+        #[structopt(long)]
+        force: bool
+    },
+    /// Prints the current state of every imported source
+    Status,
+    /// Prints the addresses of a postcode
+    Lookup {
+        postcode: String,
+        /// Can include the letter and addition, e.g. 12A-1
+        number: Option<String>,
+        /// ISO 3166-1 alpha-2 code
+        #[structopt(long)]
+        country: Option<String>
+    },
+}
+
+impl Default for Command {
+    fn default() -> Self {
+        Command::Serve { no_refresh: false }
+    }
+}
+
+#[derive(Debug)]
+pub enum CommandError {
+    Io(io::Error),
+    Pool(r2d2::Error),
+    Database(diesel::result::Error),
+    Migration(diesel_migrations::RunMigrationsError),
+    Refresh(RefreshError),
+    InvalidArgument(String),
+    DataNotReady,
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            CommandError::Io(err) => write!(f, "IO error: {}", err),
+            CommandError::Pool(err) => write!(f, "Could not get a database connection: {}", err),
+            CommandError::Database(err) => write!(f, "Database error: {}", err),
+            CommandError::Migration(err) => write!(f, "Error while running migrations: {}", err),
+            CommandError::Refresh(err) => write!(f, "Import failed: {}", err),
+            CommandError::InvalidArgument(message) => write!(f, "{}", message),
+            CommandError::DataNotReady => write!(f, "No address data has been imported yet"),
+        }
+    }
+}
+
+impl From<io::Error> for CommandError {
+    fn from(error: io::Error) -> Self {
+        CommandError::Io(error)
+    }
+}
+
+impl From<r2d2::Error> for CommandError {
+    fn from(error: r2d2::Error) -> Self {
+        CommandError::Pool(error)
+    }
+}
+
+impl From<diesel::result::Error> for CommandError {
+    fn from(error: diesel::result::Error) -> Self {
+        CommandError::Database(error)
+    }
+}
+
+impl From<diesel_migrations::RunMigrationsError> for CommandError {
+    fn from(error: diesel_migrations::RunMigrationsError) -> Self {
+        CommandError::Migration(error)
+    }
+}
+
+impl From<RefreshError> for CommandError {
+    fn from(error: RefreshError) -> Self {
+        CommandError::Refresh(error)
+    }
+}
+
+impl From<PostcodeError> for CommandError {
+    fn from(error: PostcodeError) -> Self {
+        CommandError::InvalidArgument(error.to_string())
+    }
+}
+
+pub async fn run(command: Command, config: Config) -> Result<(), CommandError> {
+    let pool = init_connection_pool(&config.database)?;
+    match command {
+        Command::Serve { no_refresh } => serve(config, pool, !no_refresh).await,
+        Command::Migrate => migrate(&pool),
+        Command::Import { source, force } => import(&config, &pool, source, force).await,
+        Command::Status => status(&pool),
+        Command::Lookup { postcode, number, country } => {
+            lookup(&config, &pool, &postcode, number, country)
+        },
+    }
+}
+
+async fn serve(config: Config, pool: Pool, refresh: bool) -> Result<(), CommandError> {
+    // Nothing else is running yet, no need to move the migrations off the event loop
+    crate::embedded_migrations::run(&pool.get()?)?;
+
+    // Data is refreshed in the background, so that the server starts even when
+    // the data can't be fetched. Until an import succeeds, lookups answer
+    // with a data_not_ready error and /readyz reports the service as not ready.
+    let monitor = RefreshMonitor::default();
+    let shutdown = Shutdown::default();
+    let refresher_pool = pool.clone();
+    let mut state_refresher = StateRefresher::new(&config, true, monitor.clone());
+    // Without the refresher, the admin commands are rejected
+    let control = state_refresher.control();
+    let refresher_shutdown = shutdown.clone();
+    let refresher = async move {
+        if refresh {
+            state_refresher.start(&refresher_pool, &refresher_shutdown).await;
+        } else {
+            info!("Data refresh is disabled, data is expected to be imported separately");
+        }
+    };
+
+    let api_config = config.api.clone();
+    let server = HttpServer::new(move || {
+        App::new()
+            .data(pool.clone())
+            .data(monitor.clone())
+            .data(control.clone())
+            .wrap(Logger::default())
+            .wrap_fn(track_request)
+            .wrap_fn(|req, srv| {
+                let request_id = RequestId::from_service_request(&req);
+                srv.call(req).map(move |res| {
+                    res.map(|mut res| {
+                        if let Ok(value) = HeaderValue::from_str(&request_id.0) {
+                            res.headers_mut()
+                                .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+                        }
+                        res
+                    })
+                })
+            })
+            .configure(api::configure(api_config.clone()))
+            .default_service(web::route().to(not_found))
+    })
+    .shutdown_timeout(config.server.shutdown_timeout.as_secs())
+    .bind(&config.server.bind_address)?
+    .run();
+
+    // On SIGTERM the server stops accepting connections, and resolves once
+    // the in-flight requests are completed (or the shutdown timeout expires).
+    // The refresher is stopped after it, as the API may still be using it.
+    let server = async {
+        let result = server.await;
+        info!("Server stopped, stopping the data refresh...");
+        shutdown.request();
+        result
+    };
+    let (result, ()) = futures::join!(server, refresher);
+    result?;
+
+    Ok(())
+}
+
+fn migrate(pool: &Pool) -> Result<(), CommandError> {
+    let conn = pool.get()?;
+    crate::embedded_migrations::run_with_output(&conn, &mut io::stdout())?;
+    Ok(())
+}
+
+async fn import(
+    config: &Config,
+    pool: &Pool,
+    source: Option<String>,
+    force: bool
+) -> Result<(), CommandError> {
+    let mut refresh = config.refresh_config();
+    refresh.force = force;
+    if let Some(source) = source {
+        refresh.location = import_location(&source, &config.data.remote)?;
+    }
+
+    let shutdown = Shutdown::default();
+    let signal_shutdown = shutdown.clone();
+    actix_rt::spawn(async move {
+        termination_signal().await;
+        info!("Shutdown requested, aborting the import...");
+        signal_shutdown.request();
+    });
+
+    recover_interrupted_imports(pool, &refresh).await?;
+    refresh_state(pool, &refresh, &RefreshMonitor::default(), &shutdown).await?;
+    Ok(())
+}
+
+/// Resolves on SIGTERM or SIGINT
+async fn termination_signal() {
+    let interrupt = signal::ctrl_c();
+    pin_mut!(interrupt);
+    match signal::unix::signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            future::select(interrupt, terminate.recv().boxed_local()).await;
+        },
+        Err(_) => {
+            let _ = interrupt.await;
+        },
+    }
+}
+
+/// A url is the location of a state index, anything else a local path
+pub fn import_location(source: &str, remote: &RemoteConfig) -> Result<DataLocation, CommandError> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        return Ok(DataLocation::Remote(RemoteConfig {
+            state_info_url: source.to_owned(),
+            ..remote.clone()
+        }));
+    }
+
+    let path = PathBuf::from(source);
+    if !path.exists() {
+        return Err(CommandError::InvalidArgument(format!("{} doesn't exist", source)));
+    }
+    Ok(DataLocation::new(Some(&path), remote))
+}
+
+fn status(pool: &Pool) -> Result<(), CommandError> {
+    let conn = pool.get()?;
+    let states = current_states(&conn)?;
+    print_json(&states);
+    Ok(())
+}
+
+fn lookup(
+    config: &Config,
+    pool: &Pool,
+    postcode: &str,
+    number: Option<String>,
+    country: Option<String>
+) -> Result<(), CommandError> {
+    let country = country.map(|c| c.trim().to_uppercase());
+    let postcode = normalize_postcode(country.as_deref(), postcode)?;
+    let number = match number {
+        Some(value) => Some(
+            parse_house_number(&value)
+                .ok_or_else(|| CommandError::InvalidArgument("number must start with digits".to_owned()))?
+        ),
+        None => None,
+    };
+    let filter = HouseNumberFilter {
+        number: number.as_ref().map(|n| n.number),
+        letter: number.as_ref().and_then(|n| n.letter.clone()),
+        addition: number.and_then(|n| n.addition),
+        mode: MatchMode::Prefix
+    };
+
+    let conn = pool.get()?;
+    let page = get_addresses(
+        &conn,
+        country.as_deref(),
+        &postcode,
+        &filter,
+        config.api.addresses_max_limit,
+        None
+    )?;
+    if page.addresses.is_empty() && !has_state(&conn)? {
+        return Err(CommandError::DataNotReady);
+    }
+    print_json(&page.addresses);
+    if page.next_cursor.is_some() {
+        eprintln!("Only the first {} addresses are listed", config.api.addresses_max_limit);
+    }
+
+    Ok(())
+}
+
+fn print_json<T: serde::Serialize>(value: &T) {
+    println!("{}", serde_json::to_string_pretty(value).expect("Error serializing output"));
+}
@@ -0,0 +1,375 @@
+use std::env;
+use std::fmt::Formatter;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use dotenv::dotenv;
+use serde::{Deserialize, Deserializer};
+
+use crate::data::state::location::DataLocation;
+use crate::data::state::remote::RemoteConfig;
+use crate::data::state::RefreshConfig;
+use crate::data::state::source::Source;
+use crate::utils::ExistsExtension;
+
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+const DEFAULT_BIND_ADDRESS: &str = "0.0.0.0:3000";
+const DEFAULT_LOG_LEVEL: &str = "info";
+const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_DB_POOL_SIZE: u32 = 15;
+const DEFAULT_ADDRESSES_MAX_LIMIT: i64 = 200;
+const DEFAULT_DATA_SOURCES: &str = "nl/countrywide";
+const DEFAULT_REFRESH_INTERVAL_SECS: u64 = 3600 * 24;
+const DEFAULT_REFRESH_RETRY_INTERVAL_SECS: u64 = 60 * 15;
+
+/// Runtime configuration, read from a TOML file (`config.toml`, or the path
+/// in `CONFIG_FILE`) and overridden by environment variables.
+/// Every setting has a default, except the database url.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub api: ApiConfig,
+    pub data: DataConfig
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Overridden by `BIND_ADDRESS`
+    pub bind_address: String,
+    /// Filters of the logs, in the `env_logger` format. Overridden by `RUST_LOG`
+    pub log_level: String,
+    /// How long in-flight requests are given to complete once a shutdown
+    /// is requested. Overridden by `SHUTDOWN_TIMEOUT_SECS`
+    #[serde(rename = "shutdown_timeout_secs", deserialize_with = "duration_secs")]
+    pub shutdown_timeout: Duration
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_address: DEFAULT_BIND_ADDRESS.to_owned(),
+            log_level: DEFAULT_LOG_LEVEL.to_owned(),
+            shutdown_timeout: Duration::from_secs(DEFAULT_SHUTDOWN_TIMEOUT_SECS)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// Overridden by `DATABASE_URL`
+    pub url: String,
+    /// Overridden by `DATABASE_POOL_SIZE`
+    pub pool_size: u32,
+    /// Skips the verification of the server certificate by the bulk loader
+    /// connection, overridden by `DATABASE_INSECURE_TLS`
+    pub insecure_tls: bool
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            url: String::new(),
+            pool_size: DEFAULT_DB_POOL_SIZE,
+            insecure_tls: false
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    /// Maximum number of addresses returned by a single query,
+    /// overridden by `ADDRESSES_MAX_LIMIT`
+    pub addresses_max_limit: i64,
+    /// Bearer token of the admin endpoints, which are disabled without it.
+    /// Overridden by `ADMIN_TOKEN`
+    pub admin_token: Option<String>
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        ApiConfig {
+            addresses_max_limit: DEFAULT_ADDRESSES_MAX_LIMIT,
+            admin_token: None
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DataConfig {
+    /// OpenAddresses source ids, overridden by a comma separated list in `DATA_SOURCES`
+    pub sources: Vec<String>,
+    /// Local data, instead of downloading it (see `DataLocation`).
+    /// Overridden by `DATA_LOCATION`
+    pub location: Option<PathBuf>,
+    /// Overridden by `DATA_REFRESH_INTERVAL_SECS`
+    #[serde(rename = "refresh_interval_secs", deserialize_with = "duration_secs")]
+    pub refresh_interval: Duration,
+    /// Interval after a failed refresh, overridden by `DATA_REFRESH_RETRY_INTERVAL_SECS`
+    #[serde(rename = "refresh_retry_interval_secs", deserialize_with = "duration_secs")]
+    pub refresh_retry_interval: Duration,
+    pub remote: RemoteConfig
+}
+
+impl Default for DataConfig {
+    fn default() -> Self {
+        DataConfig {
+            sources: vec![DEFAULT_DATA_SOURCES.to_owned()],
+            location: None,
+            refresh_interval: Duration::from_secs(DEFAULT_REFRESH_INTERVAL_SECS),
+            refresh_retry_interval: Duration::from_secs(DEFAULT_REFRESH_RETRY_INTERVAL_SECS),
+            remote: RemoteConfig::default()
+        }
+    }
+}
+
+impl DataConfig {
+    pub fn sources(&self) -> Vec<Source> {
+        self.sources
+            .iter()
+            .map(|id| Source::new(id))
+            .collect()
+    }
+
+    pub fn location(&self) -> DataLocation {
+        DataLocation::new(self.location.as_ref(), &self.remote)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read { path: PathBuf, error: std::io::Error },
+    Parse { path: PathBuf, error: toml::de::Error },
+    /// An environment variable couldn't be parsed
+    InvalidVar { name: String, value: String },
+    Invalid { field: &'static str, message: String },
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            ConfigError::Read { path, error } =>
+                write!(f, "Couldn't read config file {}: {}", path.display(), error),
+            ConfigError::Parse { path, error } =>
+                write!(f, "Invalid config file {}: {}", path.display(), error),
+            ConfigError::InvalidVar { name, value } =>
+                write!(f, "Invalid value for environment variable {}: {:?}", name, value),
+            ConfigError::Invalid { field, message } =>
+                write!(f, "Invalid config, {} {}", field, message),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config file if there is one, applies the environment
+    /// variables (including the ones of a `.env` file) and validates the result
+    pub fn load() -> Result<Config, ConfigError> {
+        dotenv().ok();
+
+        let mut config = match env::var("CONFIG_FILE") {
+            Ok(path) => Config::from_file(Path::new(&path))?,
+            Err(_) if Path::new(DEFAULT_CONFIG_FILE).is_file() =>
+                Config::from_file(Path::new(DEFAULT_CONFIG_FILE))?,
+            Err(_) => Config::default(),
+        };
+        config.apply_env(|name| env::var(name).ok())?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|error| ConfigError::Read { path: path.to_owned(), error })?;
+
+        toml::from_str(&content)
+            .map_err(|error| ConfigError::Parse { path: path.to_owned(), error })
+    }
+
+    /// Overrides the settings with the environment variables returned by `var`
+    pub fn apply_env<F>(&mut self, var: F) -> Result<(), ConfigError>
+        where
+            F: Fn(&str) -> Option<String>,
+    {
+        let var = |name: &str| var(name)
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let secs_var = |name: &str| parse_var::<u64>(&var, name)
+            .map(|secs| secs.map(Duration::from_secs));
+
+        if let Some(value) = var("BIND_ADDRESS") {
+            self.server.bind_address = value;
+        }
+        if let Some(value) = var("RUST_LOG") {
+            self.server.log_level = value;
+        }
+        if let Some(value) = secs_var("SHUTDOWN_TIMEOUT_SECS")? {
+            self.server.shutdown_timeout = value;
+        }
+        if let Some(value) = var("DATABASE_URL") {
+            self.database.url = value;
+        }
+        if let Some(value) = parse_var(&var, "DATABASE_POOL_SIZE")? {
+            self.database.pool_size = value;
+        }
+        if let Some(value) = parse_var(&var, "DATABASE_INSECURE_TLS")? {
+            self.database.insecure_tls = value;
+        }
+        if let Some(value) = parse_var(&var, "ADDRESSES_MAX_LIMIT")? {
+            self.api.addresses_max_limit = value;
+        }
+        if let Some(value) = var("ADMIN_TOKEN") {
+            self.api.admin_token = Some(value);
+        }
+
+        let data = &mut self.data;
+        if let Some(value) = var("DATA_SOURCES") {
+            data.sources = value
+                .split(',')
+                .map(str::trim)
+                .filter(|id| !id.is_empty())
+                .map(str::to_owned)
+                .collect();
+        }
+        if let Some(value) = var("DATA_LOCATION") {
+            data.location = Some(PathBuf::from(value));
+        }
+        if let Some(value) = secs_var("DATA_REFRESH_INTERVAL_SECS")? {
+            data.refresh_interval = value;
+        }
+        if let Some(value) = secs_var("DATA_REFRESH_RETRY_INTERVAL_SECS")? {
+            data.refresh_retry_interval = value;
+        }
+
+        let remote = &mut data.remote;
+        if let Some(value) = var("STATE_INFO_URL") {
+            remote.state_info_url = value;
+        }
+        if let Some(value) = var("DATA_DOWNLOAD_BASE_URL") {
+            remote.download_base_url = Some(value);
+        }
+        if let Some(value) = var("DATA_DOWNLOAD_DIR") {
+            remote.download_dir = Some(PathBuf::from(value));
+        }
+        if let Some(value) = var("DATA_HTTP_PROXY") {
+            remote.proxy = Some(value);
+        }
+        if let Some(value) = var("DATA_CA_BUNDLE") {
+            remote.ca_bundle = Some(PathBuf::from(value));
+        }
+        if let Some(value) = secs_var("DATA_CONNECT_TIMEOUT_SECS")? {
+            remote.connect_timeout = value;
+        }
+        if let Some(value) = secs_var("DATA_REQUEST_TIMEOUT_SECS")? {
+            remote.request_timeout = value;
+        }
+        if let Some(value) = var("DATA_USER_AGENT") {
+            remote.user_agent = value;
+        }
+        if let Some(value) = parse_var(&var, "DATA_RETRY_ATTEMPTS")? {
+            remote.retry.max_attempts = value;
+        }
+        if let Some(value) = secs_var("DATA_RETRY_INITIAL_DELAY_SECS")? {
+            remote.retry.initial_delay = value;
+        }
+        if let Some(value) = secs_var("DATA_RETRY_MAX_DELAY_SECS")? {
+            remote.retry.max_delay = value;
+        }
+
+        Ok(())
+    }
+
+    /// Checks the settings that would otherwise only fail once used,
+    /// possibly hours after startup
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |field: &'static str, message: &str| Err(ConfigError::Invalid {
+            field,
+            message: message.to_owned()
+        });
+
+        if self.server.bind_address.to_socket_addrs().is_err() {
+            return invalid("server.bind_address", "must be a host and port, e.g. 0.0.0.0:3000");
+        }
+        if self.database.url.is_empty() {
+            return invalid("database.url", "must be set (or DATABASE_URL)");
+        }
+        if self.database.pool_size == 0 {
+            return invalid("database.pool_size", "must be at least 1");
+        }
+        if self.api.addresses_max_limit < 1 {
+            return invalid("api.addresses_max_limit", "must be at least 1");
+        }
+        if self.api.admin_token.as_ref().exists(|token| token.trim().is_empty()) {
+            return invalid("api.admin_token", "must not be empty");
+        }
+
+        let data = &self.data;
+        if data.sources.is_empty() || data.sources.iter().any(|id| id.trim().is_empty()) {
+            return invalid("data.sources", "must list at least one source, without empty ids");
+        }
+        if data.location.as_ref().exists(|path| !path.exists()) {
+            return invalid("data.location", "must be an existing file or directory");
+        }
+        if data.refresh_interval.as_secs() == 0 || data.refresh_retry_interval.as_secs() == 0 {
+            return invalid("data.refresh_interval_secs", "and refresh_retry_interval_secs must be at least 1");
+        }
+
+        let remote = &data.remote;
+        if reqwest::Url::parse(&remote.state_info_url).is_err() {
+            return invalid("data.remote.state_info_url", "must be a valid url");
+        }
+        if remote.download_base_url.as_ref().exists(|url| reqwest::Url::parse(url).is_err()) {
+            return invalid("data.remote.download_base_url", "must be a valid url");
+        }
+        if remote.proxy.as_ref().exists(|proxy| reqwest::Proxy::all(proxy.as_str()).is_err()) {
+            return invalid("data.remote.proxy", "must be a valid proxy url");
+        }
+        if remote.ca_bundle.as_ref().exists(|path| !path.is_file()) {
+            return invalid("data.remote.ca_bundle", "must be an existing file");
+        }
+        if remote.download_dir.as_ref().exists(|path| !path.is_dir()) {
+            return invalid("data.remote.download_dir", "must be an existing directory");
+        }
+        if remote.retry.max_attempts == 0 {
+            return invalid("data.remote.retry.max_attempts", "must be at least 1");
+        }
+
+        Ok(())
+    }
+
+    /// What the data refreshes need, besides the connection pool
+    pub fn refresh_config(&self) -> RefreshConfig {
+        RefreshConfig {
+            sources: self.data.sources(),
+            location: self.data.location(),
+            database_url: self.database.url.clone(),
+            database_insecure_tls: self.database.insecure_tls,
+            force: false
+        }
+    }
+}
+
+fn parse_var<T: FromStr>(
+    var: &dyn Fn(&str) -> Option<String>,
+    name: &str
+) -> Result<Option<T>, ConfigError> {
+    match var(name) {
+        Some(value) => value
+            .parse::<T>()
+            .map(Some)
+            .map_err(|_| ConfigError::InvalidVar { name: name.to_owned(), value }),
+        None => Ok(None),
+    }
+}
+
+/// Durations are configured as a number of seconds
+pub fn duration_secs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_secs)
+}
@@ -0,0 +1,51 @@
+use crate::utils::ExistsExtension;
+
+// Larger numbers wouldn't fit in the house_number column
+const MAX_DIGITS: usize = 9;
+
+/// A house number split into its parts, following the Dutch format:
+/// the number, an optional letter directly following it (huisletter)
+/// and an optional addition (toevoeging).
+///
+/// `12A-1` is parsed as 12, `A` and `1`, while `12 bis` and `12-II`
+/// only have an addition (`BIS` and `II`). Letters and additions are
+/// upper-cased.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HouseNumber {
+    pub number: i32,
+    pub letter: Option<String>,
+    pub addition: Option<String>
+}
+
+/// Returns `None` when the value doesn't start with a number.
+/// Must be kept in sync with the backfill of the split_house_numbers migration.
+pub fn parse_house_number(value: &str) -> Option<HouseNumber> {
+    let value = value.trim();
+    let digits_end = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or_else(|| value.len());
+    if digits_end == 0 || digits_end > MAX_DIGITS {
+        return None;
+    }
+    let number = value[..digits_end].parse::<i32>().ok()?;
+
+    let mut rest = &value[digits_end..];
+    let mut chars = rest.chars();
+    let letter = match (chars.next(), chars.next()) {
+        (Some(c), next) if c.is_ascii_alphabetic() && !next.exists(char::is_ascii_alphabetic) => {
+            rest = &rest[1..];
+            Some(c.to_ascii_uppercase().to_string())
+        },
+        _ => None,
+    };
+
+    let addition = rest
+        .trim_matches(|c: char| c.is_whitespace() || c == '-' || c == '/')
+        .to_uppercase();
+
+    Some(HouseNumber {
+        number,
+        letter,
+        addition: if addition.is_empty() { None } else { Some(addition) }
+    })
+}
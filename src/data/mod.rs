@@ -1,3 +1,4 @@
+pub mod house_number;
 pub mod models;
 pub mod repo;
 pub mod schema;
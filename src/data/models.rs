@@ -5,12 +5,16 @@ use uuid::Uuid;
 use crate::data::schema::addresses;
 use crate::data::schema::states;
 
-#[derive(Queryable, Debug)]
+#[derive(Serialize, Deserialize, Queryable, Debug)]
 pub struct State {
     pub id: Uuid,
     pub hash: String,
     pub version: String,
-    pub processed_at: NaiveDateTime
+    pub processed_at: NaiveDateTime,
+    pub source: String,
+    pub inserted_count: i64,
+    pub updated_count: i64,
+    pub removed_count: i64
 }
 
 #[derive(Insertable, Debug)]
@@ -19,10 +23,15 @@ pub struct NewState<'a> {
     pub id: Uuid,
     pub hash: &'a str,
     pub version: &'a str,
-    pub processed_at: NaiveDateTime
+    pub processed_at: NaiveDateTime,
+    pub source: &'a str,
+    pub inserted_count: i64,
+    pub updated_count: i64,
+    pub removed_count: i64
 }
 
-#[derive(Serialize, Deserialize, Queryable, Debug)]
+#[derive(Serialize, Deserialize, Queryable, QueryableByName, Clone, Debug)]
+#[table_name="addresses"]
 pub struct Address {
     pub id: Uuid,
     pub lat: f64,
@@ -31,7 +40,33 @@ pub struct Address {
     pub street: String,
     pub city: String,
     pub region: String,
-    pub postcode: String
+    pub postcode: String,
+    pub country: String,
+    pub house_number: Option<i32>,
+    pub letter: Option<String>,
+    pub addition: Option<String>,
+    // OpenAddresses source the address was imported from
+    pub source: String
+}
+
+#[derive(Serialize, Deserialize, QueryableByName, Debug)]
+pub struct NearestAddress {
+    #[serde(flatten)]
+    #[diesel(embed)]
+    pub address: Address,
+    // Distance to the requested coordinates, in meters
+    #[sql_type = "diesel::sql_types::Float8"]
+    pub distance: f64
+}
+
+#[derive(Serialize, Deserialize, QueryableByName, Debug)]
+pub struct RankedAddress {
+    #[serde(flatten)]
+    #[diesel(embed)]
+    pub address: Address,
+    // Relevance of the address for the search terms, higher is better
+    #[sql_type = "diesel::sql_types::Float4"]
+    pub score: f32
 }
 
 #[derive(Insertable, Debug)]
@@ -44,11 +79,77 @@ pub struct NewAddress<'a> {
     pub street: &'a str,
     pub city: &'a str,
     pub region: &'a str,
-    pub postcode: &'a str
+    pub postcode: &'a str,
+    pub country: &'a str,
+    pub house_number: Option<i32>,
+    pub letter: Option<String>,
+    pub addition: Option<String>,
+    pub source: &'a str
+}
+
+/// Change of an address between two versions of its source
+#[derive(Serialize, Deserialize, QueryableByName, Debug)]
+pub struct AddressChange {
+    // Only used for pagination
+    #[serde(skip)]
+    #[sql_type = "diesel::sql_types::BigInt"]
+    pub id: i64,
+    /// Version of the state in which the change appeared
+    #[sql_type = "diesel::sql_types::Text"]
+    pub version: String,
+    #[sql_type = "diesel::sql_types::Text"]
+    pub source: String,
+    #[sql_type = "diesel::sql_types::Timestamp"]
+    pub processed_at: NaiveDateTime,
+    /// One of `added`, `removed`, `moved` or `renamed`
+    #[sql_type = "diesel::sql_types::Text"]
+    pub change: String,
+    #[sql_type = "diesel::sql_types::Text"]
+    pub postcode: String,
+    #[sql_type = "diesel::sql_types::Text"]
+    pub number: String,
+    #[sql_type = "diesel::sql_types::Text"]
+    pub country: String,
+    #[sql_type = "diesel::sql_types::Nullable<diesel::sql_types::Text>"]
+    pub street: Option<String>,
+    #[sql_type = "diesel::sql_types::Nullable<diesel::sql_types::Text>"]
+    pub city: Option<String>,
+    #[sql_type = "diesel::sql_types::Nullable<diesel::sql_types::Float8>"]
+    pub lat: Option<f64>,
+    #[sql_type = "diesel::sql_types::Nullable<diesel::sql_types::Float8>"]
+    pub lon: Option<f64>,
+    #[sql_type = "diesel::sql_types::Nullable<diesel::sql_types::Text>"]
+    pub previous_street: Option<String>,
+    #[sql_type = "diesel::sql_types::Nullable<diesel::sql_types::Text>"]
+    pub previous_city: Option<String>,
+    #[sql_type = "diesel::sql_types::Nullable<diesel::sql_types::Float8>"]
+    pub previous_lat: Option<f64>,
+    #[sql_type = "diesel::sql_types::Nullable<diesel::sql_types::Float8>"]
+    pub previous_lon: Option<f64>
+}
+
+/// Number of addresses of a region
+#[derive(Serialize, Deserialize, QueryableByName, Debug)]
+pub struct RegionCount {
+    #[sql_type = "diesel::sql_types::Text"]
+    pub country: String,
+    #[sql_type = "diesel::sql_types::Text"]
+    pub region: String,
+    #[sql_type = "diesel::sql_types::BigInt"]
+    pub count: i64
+}
+
+/// Number of addresses of a source
+#[derive(QueryableByName, Debug)]
+pub struct SourceCount {
+    #[sql_type = "diesel::sql_types::Text"]
+    pub source: String,
+    #[sql_type = "diesel::sql_types::BigInt"]
+    pub count: i64
 }
 
 // Used as CSV record model
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "UPPERCASE")]
 pub struct AddressRecord {
     pub lon: f32,
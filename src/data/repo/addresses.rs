@@ -1,72 +1,493 @@
-use std::collections::HashMap;
-
-use diesel::pg::upsert::excluded;
+use diesel::dsl::sql;
+use diesel::expression::SqlLiteral;
 use diesel::prelude::*;
-use uuid::Uuid;
+use diesel::sql_types::{Integer, Text};
+use serde::Deserialize;
+
+use crate::data::models::{Address, NearestAddress, RankedAddress, RegionCount, SourceCount};
+use crate::metrics::ADDRESS_QUERY_DURATION;
+use crate::utils::ExistsExtension;
+
+const EARTH_RADIUS_METERS: f64 = 6_371_008.8;
+const METERS_PER_DEGREE_LAT: f64 = 111_320.0;
+/// Addresses without a house number are sorted after the others,
+/// house numbers have at most 9 digits.
+const NO_HOUSE_NUMBER: i32 = i32::MAX;
+
+/// Position right after the last address of a page, following the order of
+/// the addresses_postcode_order index: by house number, then by number and
+/// country. The postcode is not part of it since it is always filtered on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddressCursor {
+    pub house_number: Option<i32>,
+    pub number: String,
+    pub country: String
+}
+
+impl AddressCursor {
+    /// Opaque representation given to clients
+    pub fn encode(&self) -> String {
+        let house_number = self.house_number.map(|n| n.to_string()).unwrap_or_default();
+        format!("{}:{}:{}", self.country, house_number, self.number)
+            .bytes()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    pub fn decode(value: &str) -> Option<Self> {
+        if value.len() % 2 == 1 || !value.is_ascii() {
+            return None;
+        }
+        let bytes = (0..value.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&value[i..i + 2], 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        let decoded = String::from_utf8(bytes).ok()?;
+        let mut parts = decoded.splitn(3, ':');
+        let country = parts.next()?.to_owned();
+        let house_number = match parts.next()? {
+            "" => None,
+            value => Some(value.parse::<i32>().ok()?),
+        };
+
+        Some(AddressCursor {
+            house_number,
+            country,
+            number: parts.next()?.to_owned()
+        })
+    }
 
-use crate::data::models::{Address, AddressRecord, NewAddress};
-use crate::db::Pool;
+    /// Whether the address comes after the cursor
+    pub fn precedes(&self, address: &Address) -> bool {
+        let key = (
+            self.house_number.unwrap_or(NO_HOUSE_NUMBER),
+            self.number.as_str(),
+            self.country.as_str()
+        );
+        sort_key(address) > key
+    }
+}
+
+impl From<&Address> for AddressCursor {
+    fn from(address: &Address) -> Self {
+        AddressCursor {
+            house_number: address.house_number,
+            number: address.number.clone(),
+            country: address.country.clone()
+        }
+    }
+}
 
-const ADDRESSES_RESULT_LIMIT: i64 = 200;
+/// Order of the addresses of a postcode, the same as `get_addresses`:
+/// numbers and countries are compared byte by byte, as with the "C" collation.
+pub fn sort_key(address: &Address) -> (i32, &str, &str) {
+    (
+        address.house_number.unwrap_or(NO_HOUSE_NUMBER),
+        &address.number,
+        &address.country
+    )
+}
+
+// Sort expressions of `get_addresses`, they must match the
+// addresses_postcode_order index for it to be used.
+fn sort_house_number() -> SqlLiteral<Integer> {
+    sql("coalesce(house_number, 2147483647)")
+}
+
+fn sort_number() -> SqlLiteral<Text> {
+    sql(r#"number COLLATE "C""#)
+}
+
+fn sort_country() -> SqlLiteral<Text> {
+    sql(r#"country COLLATE "C""#)
+}
+
+#[derive(Debug)]
+pub struct AddressPage {
+    pub addresses: Vec<Address>,
+    /// Set when more addresses match the query
+    pub next_cursor: Option<AddressCursor>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    /// Letter and addition must be equal, or absent when not specified
+    Exact,
+    /// Letter and addition must start with the given values, if any
+    Prefix,
+}
+
+/// House number filter of an addresses query.
+/// The number itself is always matched exactly.
+#[derive(Debug)]
+pub struct HouseNumberFilter {
+    pub number: Option<i32>,
+    pub letter: Option<String>,
+    pub addition: Option<String>,
+    pub mode: MatchMode
+}
+
+impl HouseNumberFilter {
+    /// Same as the filter applied by `get_addresses`, for addresses
+    /// already loaded in memory.
+    pub fn matches(&self, address: &Address) -> bool {
+        fn starts_with_ignore_case(value: &Option<String>, prefix: &str) -> bool {
+            value.exists(|v| v.to_uppercase().starts_with(&prefix.to_uppercase()))
+        }
+        fn eq_ignore_case(value: &Option<String>, expected: &Option<String>) -> bool {
+            match (value, expected) {
+                (Some(v), Some(e)) => v.eq_ignore_ascii_case(e),
+                (None, None) => true,
+                _ => false,
+            }
+        }
+
+        if self.number.is_some() && address.house_number != self.number {
+            return false;
+        }
+        match self.mode {
+            MatchMode::Exact => {
+                self.number.is_none() || (
+                    eq_ignore_case(&address.letter, &self.letter)
+                        && eq_ignore_case(&address.addition, &self.addition)
+                )
+            },
+            MatchMode::Prefix => {
+                self.letter.iter().all(|l| starts_with_ignore_case(&address.letter, l))
+                    && self.addition.iter().all(|a| starts_with_ignore_case(&address.addition, a))
+            },
+        }
+    }
+}
 
 pub fn get_addresses(
-    pool: &Pool,
+    conn: &PgConnection,
+    country_code: Option<&str>,
     pcode: &str,
-    house_number: Option<&str>
-) -> Result<Vec<Address>, diesel::result::Error> {
+    filter: &HouseNumberFilter,
+    limit: i64,
+    after: Option<&AddressCursor>
+) -> Result<AddressPage, diesel::result::Error> {
     use crate::data::schema::addresses::dsl::*;
 
+    let _timer = ADDRESS_QUERY_DURATION.with_label_values(&["postcode"]).start_timer();
     let mut query = addresses.filter(postcode.eq(pcode)).into_boxed();
-    if let Some(nb) = house_number {
-        query = query.filter(number.ilike(format!("{}%", nb)));
+    if let Some(code) = country_code {
+        query = query.filter(country.eq(code.to_uppercase()));
+    }
+    if let Some(nb) = filter.number {
+        query = query.filter(house_number.eq(nb));
     }
+    match filter.mode {
+        MatchMode::Exact => {
+            // Without a number, an exact match on the other parts isn't meaningful
+            if filter.number.is_some() {
+                query = match &filter.letter {
+                    Some(l) => query.filter(letter.eq(l.to_uppercase())),
+                    None => query.filter(letter.is_null()),
+                };
+                query = match &filter.addition {
+                    Some(a) => query.filter(addition.eq(a.to_uppercase())),
+                    None => query.filter(addition.is_null()),
+                };
+            }
+        },
+        MatchMode::Prefix => {
+            if let Some(l) = &filter.letter {
+                query = query.filter(letter.ilike(format!("{}%", escape_like(l))));
+            }
+            if let Some(a) = &filter.addition {
+                query = query.filter(addition.ilike(format!("{}%", escape_like(a))));
+            }
+        },
+    }
+
+    if let Some(cursor) = after {
+        let after_house_number = cursor.house_number.unwrap_or(NO_HOUSE_NUMBER);
+        query = query.filter(
+            sort_house_number().gt(after_house_number)
+                .or(sort_house_number().eq(after_house_number).and(
+                    sort_number().gt(&cursor.number)
+                        .or(sort_number().eq(&cursor.number).and(sort_country().gt(&cursor.country)))
+                ))
+        );
+    }
+
+    // Fetch one more address to know if there is a next page
+    let mut results: Vec<Address> = query
+        .order((sort_house_number().asc(), sort_number().asc(), sort_country().asc()))
+        .limit(limit + 1)
+        .load(conn)?;
 
-    query
-        .order(number.asc())
-        .limit(ADDRESSES_RESULT_LIMIT)
-        .load(&pool.get().unwrap())
+    let next_cursor = if results.len() as i64 > limit {
+        results.truncate(limit as usize);
+        results.last().map(AddressCursor::from)
+    } else {
+        None
+    };
+
+    Ok(AddressPage { addresses: results, next_cursor })
 }
 
-pub fn create_or_update_addresses(
+/// Addresses of the given postcodes, in any country, at most `limit`.
+/// Only the addresses with one of the given house numbers are returned
+/// for the numbered postcodes.
+pub fn get_addresses_by_postcodes(
     conn: &PgConnection,
-    records: &[AddressRecord]
-) -> Result<usize, diesel::result::Error> {
+    pcodes: &[String],
+    numbered_pcodes: &[String],
+    house_numbers: &[i32],
+    limit: i64
+) -> Result<Vec<Address>, diesel::result::Error> {
     use crate::data::schema::addresses::dsl::*;
 
-    // Filter duplicates because the same address sometimes has multiple
-    // entries in the CSV with different coordinates, for example:
-    // 4.6863255,52.3094285,1115,Kruisweg,,Hoofddorp,,Noord-Holland,2131CV,,7c3c022a3d3d5f99
-    // 4.6863538,52.3094487,1115,Kruisweg,,Hoofddorp,,Noord-Holland,2131CV,,857b39c71594b270
-    let mut address_map: HashMap<(String, String), NewAddress> =
-        HashMap::with_capacity(records.len());
-
-    for record in records {
-        let key = (record.postcode.clone(), record.number.clone());
-        address_map.insert(key, NewAddress {
-            id: Uuid::new_v4(),
-            lat: record.lat as f64,
-            lon: record.lon as f64,
-            number: record.number.as_str(),
-            street: record.street.as_str(),
-            city: record.city.as_str(),
-            region: record.region.as_str(),
-            postcode: record.postcode.as_str()
-        });
+    let _timer = ADDRESS_QUERY_DURATION.with_label_values(&["postcodes"]).start_timer();
+    addresses
+        .filter(
+            postcode.eq_any(pcodes)
+                .or(postcode.eq_any(numbered_pcodes).and(house_number.eq_any(house_numbers)))
+        )
+        .limit(limit)
+        .load(conn)
+}
+
+/// Addresses within `radius` meters of the given coordinates, closest first
+pub fn get_nearest_addresses(
+    conn: &PgConnection,
+    country_code: Option<&str>,
+    latitude: f64,
+    longitude: f64,
+    radius: f64,
+    limit: i64
+) -> Result<Vec<NearestAddress>, diesel::result::Error> {
+    use diesel::sql_types::{BigInt, Float8, Nullable, Text};
+
+    let _timer = ADDRESS_QUERY_DURATION.with_label_values(&["nearest"]).start_timer();
+    // Only consider the bounding box around the coordinates, which can use
+    // the (lat, lon) index, before computing the exact (haversine) distance.
+    let lat_delta = radius / METERS_PER_DEGREE_LAT;
+    let lon_delta = radius / (METERS_PER_DEGREE_LAT * latitude.to_radians().cos().max(0.01));
+
+    diesel::sql_query(r#"
+        SELECT * FROM (
+            SELECT addresses.*,
+                $1 * 2 * asin(sqrt(
+                    power(sin(radians(lat - $2) / 2), 2)
+                    + cos(radians($2)) * cos(radians(lat)) * power(sin(radians(lon - $3) / 2), 2)
+                )) AS distance
+            FROM addresses
+            WHERE lat BETWEEN $4 AND $5
+              AND lon BETWEEN $6 AND $7
+              AND ($8 IS NULL OR country = $8)
+        ) AS candidates
+        WHERE distance <= $9
+        ORDER BY distance
+        LIMIT $10
+    "#)
+        .bind::<Float8, _>(EARTH_RADIUS_METERS)
+        .bind::<Float8, _>(latitude)
+        .bind::<Float8, _>(longitude)
+        .bind::<Float8, _>(latitude - lat_delta)
+        .bind::<Float8, _>(latitude + lat_delta)
+        .bind::<Float8, _>(longitude - lon_delta)
+        .bind::<Float8, _>(longitude + lon_delta)
+        .bind::<Nullable<Text>, _>(country_code.map(str::to_uppercase))
+        .bind::<Float8, _>(radius)
+        .bind::<BigInt, _>(limit)
+        .load(conn)
+}
+
+/// Addresses matching all the search terms, best matches first.
+/// Each term is matched as a prefix of a word of the street, number,
+/// city or postcode.
+pub fn search_addresses(
+    conn: &PgConnection,
+    country_code: Option<&str>,
+    terms: &[String],
+    limit: i64
+) -> Result<Vec<RankedAddress>, diesel::result::Error> {
+    use diesel::sql_types::{BigInt, Nullable, Text};
+
+    let _timer = ADDRESS_QUERY_DURATION.with_label_values(&["search"]).start_timer();
+    // Terms must only contain alphanumeric characters,
+    // anything else would be interpreted as tsquery syntax.
+    let query = terms
+        .iter()
+        .filter(|term| !term.is_empty() && term.chars().all(char::is_alphanumeric))
+        .map(|term| format!("{}:*", term.to_lowercase()))
+        .collect::<Vec<String>>()
+        .join(" & ");
+
+    if query.is_empty() {
+        return Ok(vec![]);
     }
-    let new_addresses = address_map
-        .values()
-        .collect::<Vec<&NewAddress>>();
-
-    diesel::insert_into(addresses)
-        .values(new_addresses)
-        .on_conflict((postcode, number))
-        .do_update()
-        .set((
-            lat.eq(excluded(lat)),
-            lon.eq(excluded(lon)),
-            street.eq(excluded(street)),
-            city.eq(excluded(city)),
-            region.eq(excluded(region))
-        ))
+
+    // The document expression must match the addresses_search index
+    diesel::sql_query(r#"
+        SELECT addresses.*, ts_rank(document, query) AS score
+        FROM addresses,
+            to_tsquery('simple', $1) AS query,
+            to_tsvector('simple', street || ' ' || number || ' ' || city || ' ' || postcode) AS document
+        WHERE to_tsvector('simple', street || ' ' || number || ' ' || city || ' ' || postcode) @@ query
+          AND ($2 IS NULL OR country = $2)
+        ORDER BY score DESC, street, city, length(number), number
+        LIMIT $3
+    "#)
+        .bind::<Text, _>(query)
+        .bind::<Nullable<Text>, _>(country_code.map(str::to_uppercase))
+        .bind::<BigInt, _>(limit)
+        .load(conn)
+}
+
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Number of addresses per region, as of the last publication of each source
+pub fn count_addresses_by_region(conn: &PgConnection) -> Result<Vec<RegionCount>, diesel::result::Error> {
+    diesel::sql_query(r#"
+        SELECT country, region, sum(count)::BIGINT AS count
+        FROM region_counts
+        GROUP BY country, region
+        ORDER BY country, region
+    "#)
+    .load(conn)
+}
+
+/// Saves the number of addresses per region of the source, once published
+pub fn update_region_counts(conn: &PgConnection, source_id: &str) -> Result<usize, diesel::result::Error> {
+    use diesel::sql_types::Text;
+
+    diesel::sql_query("DELETE FROM region_counts WHERE source = $1")
+        .bind::<Text, _>(source_id)
+        .execute(conn)?;
+    diesel::sql_query(r#"
+        INSERT INTO region_counts (source, country, region, count)
+        SELECT source, country, region, count(*)
+        FROM addresses
+        WHERE source = $1
+        GROUP BY source, country, region
+    "#)
+        .bind::<Text, _>(source_id)
         .execute(conn)
 }
+
+/// Removes the staged addresses of the source, once published or rejected
+pub fn clear_staged_addresses(conn: &PgConnection, source_id: &str) -> Result<usize, diesel::result::Error> {
+    use crate::data::schema::addresses_staging::dsl::*;
+
+    diesel::delete(addresses_staging.filter(source.eq(source_id))).execute(conn)
+}
+
+pub fn count_staged_addresses(conn: &PgConnection, source_id: &str) -> Result<i64, diesel::result::Error> {
+    use crate::data::schema::addresses_staging::dsl::*;
+
+    addresses_staging
+        .filter(source.eq(source_id))
+        .count()
+        .get_result(conn)
+}
+
+/// Staged addresses left by imports that weren't published
+pub fn count_staged_addresses_by_source(
+    conn: &PgConnection
+) -> Result<Vec<SourceCount>, diesel::result::Error> {
+    diesel::sql_query(r#"
+        SELECT source, count(*) AS count
+        FROM addresses_staging
+        GROUP BY source
+        ORDER BY source
+    "#)
+    .load(conn)
+}
+
+/// Changes made to the addresses table when publishing an import
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ImportCounts {
+    pub inserted: i64,
+    pub updated: i64,
+    pub removed: i64
+}
+
+#[derive(QueryableByName)]
+struct UpsertCounts {
+    #[sql_type = "diesel::sql_types::BigInt"]
+    inserted: i64,
+    #[sql_type = "diesel::sql_types::BigInt"]
+    updated: i64
+}
+
+/// Replaces the addresses of the source with its staged ones: addresses
+/// missing from the staging table are removed, the others are inserted or
+/// updated when they changed. Addresses staged for other sources are ignored.
+///
+/// An address listed by several sources belongs to the first one that
+/// imported it, and is only updated or removed by that source. Addresses
+/// without a source are adopted by the first import containing them.
+///
+/// Must be run in the same transaction as the creation of the new state,
+/// so that readers either see the previous or the new dataset.
+pub fn publish_staged_addresses(
+    conn: &PgConnection,
+    source_id: &str
+) -> Result<ImportCounts, diesel::result::Error> {
+    use diesel::sql_types::Text;
+
+    let removed = diesel::sql_query(r#"
+        DELETE FROM addresses
+        WHERE source = $1
+          AND NOT EXISTS (
+              SELECT 1 FROM addresses_staging AS staged
+              WHERE staged.source = $1
+                AND staged.postcode = addresses.postcode
+                AND staged.number = addresses.number
+                AND staged.country = addresses.country
+          )
+    "#)
+        .bind::<Text, _>(source_id)
+        .execute(conn)?;
+
+    // xmax is only set on rows that were updated by the upsert
+    let counts: UpsertCounts = diesel::sql_query(r#"
+        WITH upserted AS (
+            INSERT INTO addresses
+                (id, lat, lon, number, street, city, region, postcode, country, house_number, letter, addition, source)
+            SELECT id, lat, lon, number, street, city, region, postcode, country, house_number, letter, addition, source
+            FROM addresses_staging
+            WHERE source = $1
+            ON CONFLICT (postcode, number, country) DO UPDATE SET
+                lat = excluded.lat,
+                lon = excluded.lon,
+                street = excluded.street,
+                city = excluded.city,
+                region = excluded.region,
+                house_number = excluded.house_number,
+                letter = excluded.letter,
+                addition = excluded.addition,
+                source = excluded.source
+            WHERE addresses.source IN (excluded.source, '')
+              AND (addresses.lat, addresses.lon, addresses.street, addresses.city, addresses.region,
+                   addresses.house_number, addresses.letter, addresses.addition, addresses.source)
+                IS DISTINCT FROM
+                  (excluded.lat, excluded.lon, excluded.street, excluded.city, excluded.region,
+                   excluded.house_number, excluded.letter, excluded.addition, excluded.source)
+            RETURNING xmax = 0 AS inserted
+        )
+        SELECT
+            count(*) FILTER (WHERE inserted) AS inserted,
+            count(*) FILTER (WHERE NOT inserted) AS updated
+        FROM upserted
+    "#)
+        .bind::<Text, _>(source_id)
+        .get_result(conn)?;
+
+    Ok(ImportCounts {
+        inserted: counts.inserted,
+        updated: counts.updated,
+        removed: removed as i64
+    })
+}
@@ -0,0 +1,241 @@
+use std::io::Write;
+use std::time::Instant;
+
+use log::info;
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use postgres::Client;
+use postgres_openssl::MakeTlsConnector;
+use uuid::Uuid;
+
+use crate::data::house_number::parse_house_number;
+use crate::data::models::{AddressRecord, NewAddress};
+use crate::data::state::error::RefreshError;
+
+const COLUMNS: &str =
+    "id, lat, lon, number, street, city, region, postcode, country, house_number, letter, addition, source";
+
+/// Loads addresses in the staging table with `COPY FROM STDIN`, which is
+/// much faster than inserting them in batches.
+///
+/// Diesel doesn't support `COPY`, so this uses its own connection.
+pub struct BulkLoader {
+    client: Client
+}
+
+impl BulkLoader {
+    /// Connects with TLS according to the `sslmode` of the url, verifying the
+    /// server certificate unless `insecure_tls` is set.
+    pub fn connect(database_url: &str, insecure_tls: bool) -> Result<Self, RefreshError> {
+        let (url, tls) = loader_url(database_url)?;
+        if insecure_tls && tls.verify {
+            return Err(RefreshError::IO(Box::new(
+                "Insecure TLS can't be used with sslmode verify-ca or verify-full"
+            )));
+        }
+
+        let mut builder = SslConnector::builder(SslMethod::tls())?;
+        if let Some(root_cert) = &tls.root_cert {
+            builder.set_ca_file(root_cert)?;
+        }
+        if insecure_tls {
+            builder.set_verify(SslVerifyMode::NONE);
+        }
+        let mut connector = MakeTlsConnector::new(builder.build());
+        if !tls.verify_hostname {
+            connector.set_callback(|config, _| {
+                config.set_verify_hostname(false);
+                Ok(())
+            });
+        }
+        let client = Client::connect(&url, connector)?;
+        Ok(BulkLoader { client })
+    }
+
+    /// Replaces the staged addresses of the source with the given records,
+    /// returns the number of records read.
+    ///
+    /// Records are first copied to a temporary table, then merged in the
+    /// staging table in a single statement, removing duplicates: the same
+    /// address sometimes has multiple entries in the CSV with different
+    /// coordinates, for example:
+    /// 4.6863255,52.3094285,1115,Kruisweg,,Hoofddorp,,Noord-Holland,2131CV,,7c3c022a3d3d5f99
+    /// 4.6863538,52.3094487,1115,Kruisweg,,Hoofddorp,,Noord-Holland,2131CV,,857b39c71594b270
+    pub fn load<I, E>(
+        &mut self,
+        source_id: &str,
+        country_code: &str,
+        records: I
+    ) -> Result<usize, RefreshError>
+        where
+            I: IntoIterator<Item = Result<AddressRecord, E>>,
+            RefreshError: From<E>,
+    {
+        let mut transaction = self.client.transaction()?;
+        transaction.batch_execute(
+            "CREATE TEMPORARY TABLE addresses_load \
+            (LIKE addresses_staging INCLUDING DEFAULTS) \
+            ON COMMIT DROP"
+        )?;
+
+        let copy_start = Instant::now();
+        let mut writer = transaction.copy_in(
+            format!("COPY addresses_load ({}) FROM STDIN", COLUMNS).as_str()
+        )?;
+        let mut line = String::new();
+        let mut record_count = 0;
+        for record in records {
+            let record = record?;
+            let parsed_number = parse_house_number(&record.number);
+            let address = NewAddress {
+                id: Uuid::new_v4(),
+                lat: record.lat as f64,
+                lon: record.lon as f64,
+                number: &record.number,
+                street: &record.street,
+                city: &record.city,
+                region: &record.region,
+                postcode: &record.postcode,
+                country: country_code,
+                house_number: parsed_number.as_ref().map(|n| n.number),
+                letter: parsed_number.as_ref().and_then(|n| n.letter.clone()),
+                addition: parsed_number.and_then(|n| n.addition),
+                source: source_id
+            };
+
+            line.clear();
+            copy_row(&mut line, &address);
+            writer.write_all(line.as_bytes())?;
+            record_count += 1;
+        }
+        writer.finish()?;
+        log_throughput("Copied", record_count, copy_start);
+
+        let merge_start = Instant::now();
+        transaction.execute("DELETE FROM addresses_staging WHERE source = $1", &[&source_id])?;
+        let address_count = transaction.execute(
+            format!(
+                "INSERT INTO addresses_staging ({columns}) \
+                SELECT DISTINCT ON (postcode, number, country) {columns} \
+                FROM addresses_load \
+                ORDER BY postcode, number, country, lat, lon",
+                columns = COLUMNS
+            ).as_str(),
+            &[]
+        )?;
+        transaction.commit()?;
+        log_throughput("Merged", address_count as usize, merge_start);
+
+        Ok(record_count)
+    }
+}
+
+fn log_throughput(operation: &str, count: usize, start: Instant) {
+    let seconds = start.elapsed().as_secs_f64();
+    info!(
+        "{} {} addresses in {:.1}s ({:.0} addresses/s)",
+        operation,
+        count,
+        seconds,
+        count as f64 / seconds.max(0.001)
+    );
+}
+
+/// Row in the text format of `COPY`, columns in the order of `COLUMNS`
+fn copy_row(line: &mut String, address: &NewAddress) {
+    let id = address.id.to_string();
+    let lat = address.lat.to_string();
+    let lon = address.lon.to_string();
+    let house_number = address.house_number.map(|n| n.to_string());
+    let values = [
+        Some(id.as_str()),
+        Some(lat.as_str()),
+        Some(lon.as_str()),
+        Some(address.number),
+        Some(address.street),
+        Some(address.city),
+        Some(address.region),
+        Some(address.postcode),
+        Some(address.country),
+        house_number.as_deref(),
+        address.letter.as_deref(),
+        address.addition.as_deref(),
+        Some(address.source),
+    ];
+
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            line.push('\t');
+        }
+        match value {
+            Some(value) => escape_copy_text(line, value),
+            None => line.push_str("\\N"),
+        }
+    }
+    line.push('\n');
+}
+
+fn escape_copy_text(line: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '\\' => line.push_str("\\\\"),
+            '\t' => line.push_str("\\t"),
+            '\n' => line.push_str("\\n"),
+            '\r' => line.push_str("\\r"),
+            c => line.push(c),
+        }
+    }
+}
+
+/// TLS parameters of libpq that the postgres crate doesn't support
+struct LoaderTls {
+    /// Required by the sslmode, instead of only checked when available
+    verify: bool,
+    verify_hostname: bool,
+    root_cert: Option<String>
+}
+
+/// Url of the loader connection: the `sslmode` of libpq is mapped to one
+/// supported by the postgres crate, and `sslrootcert` is taken out of it.
+/// Urls can be given as a URI or as `key=value` pairs, like with libpq.
+fn loader_url(database_url: &str) -> Result<(String, LoaderTls), RefreshError> {
+    let is_uri = database_url.contains("://");
+    let mut parts = database_url.splitn(2, '?');
+    let (base, params) = if is_uri {
+        let base = parts.next().unwrap_or_default();
+        (base, parts.next().map(|query| query.split('&').collect()).unwrap_or_default())
+    } else {
+        ("", database_url.split_whitespace().collect::<Vec<&str>>())
+    };
+
+    let mut ssl_mode = None;
+    let mut root_cert = None;
+    let mut kept = Vec::with_capacity(params.len());
+    for param in params {
+        let mut pair = param.splitn(2, '=');
+        match (pair.next(), pair.next()) {
+            (Some("sslmode"), Some(value)) => ssl_mode = Some(value.to_owned()),
+            (Some("sslrootcert"), Some(value)) => root_cert = Some(value.to_owned()),
+            _ => kept.push(param.to_owned()),
+        }
+    }
+
+    let (mode, verify, verify_hostname) = match ssl_mode.as_deref().unwrap_or("prefer") {
+        "disable" => ("disable", false, true),
+        "allow" | "prefer" => ("prefer", false, true),
+        "require" => ("require", false, true),
+        "verify-ca" => ("require", true, false),
+        "verify-full" => ("require", true, true),
+        other => {
+            return Err(RefreshError::IO(Box::new(format!("Unsupported sslmode {} in the database url", other))));
+        },
+    };
+    kept.push(format!("sslmode={}", mode));
+
+    let url = if is_uri {
+        format!("{}?{}", base, kept.join("&"))
+    } else {
+        kept.join(" ")
+    };
+
+    Ok((url, LoaderTls { verify, verify_hostname, root_cert }))
+}
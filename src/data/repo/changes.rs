@@ -0,0 +1,115 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use crate::data::models::AddressChange;
+
+#[derive(Debug)]
+pub struct ChangePage {
+    pub changes: Vec<AddressChange>,
+    /// Id of the last change of the page, when there are more
+    pub next_cursor: Option<i64>
+}
+
+/// Records the differences between the staged and the current addresses
+/// of the source, as changes of the given state. Addresses belonging to
+/// other sources are left out, as publishing doesn't change them
+/// (see `publish_staged_addresses`).
+/// Must be run before the staged addresses are published.
+pub fn record_address_changes(
+    conn: &PgConnection,
+    source_id: &str,
+    state_id: Uuid
+) -> Result<usize, diesel::result::Error> {
+    use diesel::sql_types::Text;
+
+    diesel::sql_query(r#"
+        INSERT INTO address_changes (
+            state_id, change, postcode, number, country,
+            street, city, lat, lon,
+            previous_street, previous_city, previous_lat, previous_lon
+        )
+        SELECT $1, 'added', staged.postcode, staged.number, staged.country,
+            staged.street, staged.city, staged.lat, staged.lon,
+            NULL, NULL, NULL, NULL
+        FROM addresses_staging AS staged
+        WHERE staged.source = $2
+          AND NOT EXISTS (
+            SELECT 1 FROM addresses
+            WHERE addresses.postcode = staged.postcode
+              AND addresses.number = staged.number
+              AND addresses.country = staged.country
+        )
+        UNION ALL
+        SELECT $1, 'removed', addresses.postcode, addresses.number, addresses.country,
+            NULL, NULL, NULL, NULL,
+            addresses.street, addresses.city, addresses.lat, addresses.lon
+        FROM addresses
+        WHERE addresses.source = $2
+          AND NOT EXISTS (
+              SELECT 1 FROM addresses_staging AS staged
+              WHERE staged.source = $2
+                AND staged.postcode = addresses.postcode
+                AND staged.number = addresses.number
+                AND staged.country = addresses.country
+          )
+        UNION ALL
+        SELECT $1, changed.change, staged.postcode, staged.number, staged.country,
+            staged.street, staged.city, staged.lat, staged.lon,
+            addresses.street, addresses.city, addresses.lat, addresses.lon
+        FROM addresses_staging AS staged
+        JOIN addresses
+            ON addresses.postcode = staged.postcode
+           AND addresses.number = staged.number
+           AND addresses.country = staged.country
+           AND addresses.source IN (staged.source, '')
+        CROSS JOIN LATERAL (
+            SELECT 'moved' AS change
+            WHERE (addresses.lat, addresses.lon) IS DISTINCT FROM (staged.lat, staged.lon)
+            UNION ALL
+            SELECT 'renamed'
+            WHERE (addresses.street, addresses.city) IS DISTINCT FROM (staged.street, staged.city)
+        ) AS changed
+        WHERE staged.source = $2
+    "#)
+        .bind::<diesel::sql_types::Uuid, _>(state_id)
+        .bind::<Text, _>(source_id)
+        .execute(conn)
+}
+
+/// Changes of the states processed after the given time, oldest first
+pub fn get_changes(
+    conn: &PgConnection,
+    since: NaiveDateTime,
+    country_code: Option<&str>,
+    limit: i64,
+    after: Option<i64>
+) -> Result<ChangePage, diesel::result::Error> {
+    use diesel::sql_types::{BigInt, Nullable, Text, Timestamp};
+
+    let mut changes: Vec<AddressChange> = diesel::sql_query(r#"
+        SELECT address_changes.*, states.version, states.source, states.processed_at
+        FROM address_changes
+        JOIN states ON states.id = address_changes.state_id
+        WHERE states.processed_at > $1
+          AND ($2 IS NULL OR address_changes.country = $2)
+          AND ($3 IS NULL OR address_changes.id > $3)
+        ORDER BY address_changes.id
+        LIMIT $4
+    "#)
+        .bind::<Timestamp, _>(since)
+        .bind::<Nullable<Text>, _>(country_code.map(str::to_uppercase))
+        .bind::<Nullable<BigInt>, _>(after)
+        // One more to know whether there is a next page
+        .bind::<BigInt, _>(limit + 1)
+        .load(conn)?;
+
+    let next_cursor = if changes.len() as i64 > limit {
+        changes.truncate(limit as usize);
+        changes.last().map(|change| change.id)
+    } else {
+        None
+    };
+
+    Ok(ChangePage { changes, next_cursor })
+}
@@ -0,0 +1,52 @@
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Bool};
+use log::error;
+
+use crate::db::PooledConnection;
+
+/// Key of the advisory lock, only needs to be unique within the database
+const IMPORT_LOCK_KEY: i64 = 0x706f_7374_636f_6465;
+
+#[derive(QueryableByName)]
+struct LockResult {
+    #[sql_type = "Bool"]
+    locked: bool
+}
+
+/// Postgres advisory lock held for the whole import of a source, from its
+/// download to its publication. Imports share the staging table (and the
+/// download directory), so only one of them runs at a time, whichever
+/// instance or process runs it.
+///
+/// The lock belongs to the database session: it is released when dropped,
+/// or when the connection is closed if the process is killed.
+pub struct ImportLock {
+    conn: PooledConnection
+}
+
+impl ImportLock {
+    /// `None` when another import holds the lock
+    pub fn try_acquire(conn: PooledConnection) -> Result<Option<ImportLock>, diesel::result::Error> {
+        let result: LockResult = diesel::sql_query("SELECT pg_try_advisory_lock($1) AS locked")
+            .bind::<BigInt, _>(IMPORT_LOCK_KEY)
+            .get_result(&*conn)?;
+
+        Ok(if result.locked { Some(ImportLock { conn }) } else { None })
+    }
+
+    /// Connection holding the lock, to run the import with
+    pub fn conn(&self) -> &PgConnection {
+        &self.conn
+    }
+}
+
+impl Drop for ImportLock {
+    fn drop(&mut self) {
+        let result = diesel::sql_query("SELECT pg_advisory_unlock($1)")
+            .bind::<BigInt, _>(IMPORT_LOCK_KEY)
+            .execute(&*self.conn);
+        if let Err(err) = result {
+            error!("Error releasing the import lock: {}", err);
+        }
+    }
+}
@@ -1,2 +1,5 @@
 pub mod addresses;
+pub mod bulk_load;
+pub mod changes;
+pub mod import_lock;
 pub mod states;
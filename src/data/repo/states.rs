@@ -4,33 +4,90 @@ use diesel::prelude::*;
 use uuid::Uuid;
 
 use crate::data::models::{NewState, State};
+use crate::data::repo::addresses::ImportCounts;
 use crate::data::state::StateInfo;
 
-pub fn current_state(conn: &PgConnection) -> Result<Option<State>, diesel::result::Error> {
+pub fn current_state(
+    conn: &PgConnection,
+    state_source: &str
+) -> Result<Option<State>, diesel::result::Error> {
     use crate::data::schema::states::dsl::*;
 
     states
+        .filter(source.eq(state_source))
         .order(processed_at.desc())
         .limit(1)
         .first(conn)
         .optional()
 }
 
+/// Current state of every source that was imported
+pub fn current_states(conn: &PgConnection) -> Result<Vec<State>, diesel::result::Error> {
+    use crate::data::schema::states::dsl::*;
+
+    states
+        .distinct_on(source)
+        .order((source, processed_at.desc()))
+        .load(conn)
+}
+
+pub fn has_state(conn: &PgConnection) -> Result<bool, diesel::result::Error> {
+    use crate::data::schema::states::dsl::*;
+    use diesel::dsl::exists;
+
+    diesel::select(exists(states.select(id))).get_result(conn)
+}
+
+/// Creates the state of a new import, its counts are set
+/// once the import is published.
 pub fn create_new_state(
     conn: &PgConnection,
     state_info: &StateInfo
-) -> Result<usize, diesel::result::Error> {
+) -> Result<State, diesel::result::Error> {
     use crate::data::schema::states::dsl::*;
 
     let new_state = NewState {
         id: Uuid::new_v4(),
         hash: &state_info.hash,
         version: &state_info.version,
-        processed_at: Utc::now().naive_utc()
+        processed_at: Utc::now().naive_utc(),
+        source: &state_info.source,
+        inserted_count: 0,
+        updated_count: 0,
+        removed_count: 0
     };
 
     diesel::insert_into(states)
         .values(new_state)
+        .get_result(conn)
+}
+
+pub fn set_state_counts(
+    conn: &PgConnection,
+    state_id: Uuid,
+    counts: &ImportCounts
+) -> Result<usize, diesel::result::Error> {
+    use crate::data::schema::states::dsl::*;
+
+    diesel::update(states.find(state_id))
+        .set((
+            inserted_count.eq(counts.inserted),
+            updated_count.eq(counts.updated),
+            removed_count.eq(counts.removed)
+        ))
         .execute(conn)
 }
 
+/// First state imported with the given version, from any source
+pub fn find_state_by_version(
+    conn: &PgConnection,
+    state_version: &str
+) -> Result<Option<State>, diesel::result::Error> {
+    use crate::data::schema::states::dsl::*;
+
+    states
+        .filter(version.eq(state_version))
+        .order(processed_at.asc())
+        .first(conn)
+        .optional()
+}
@@ -1,3 +1,22 @@
+table! {
+    address_changes (id) {
+        id -> Int8,
+        state_id -> Uuid,
+        change -> Text,
+        postcode -> Text,
+        number -> Text,
+        country -> Text,
+        street -> Nullable<Text>,
+        city -> Nullable<Text>,
+        lat -> Nullable<Float8>,
+        lon -> Nullable<Float8>,
+        previous_street -> Nullable<Text>,
+        previous_city -> Nullable<Text>,
+        previous_lat -> Nullable<Float8>,
+        previous_lon -> Nullable<Float8>,
+    }
+}
+
 table! {
     addresses (id) {
         id -> Uuid,
@@ -8,6 +27,38 @@ table! {
         city -> Text,
         region -> Text,
         postcode -> Text,
+        country -> Text,
+        house_number -> Nullable<Int4>,
+        letter -> Nullable<Text>,
+        addition -> Nullable<Text>,
+        source -> Text,
+    }
+}
+
+table! {
+    addresses_staging (id) {
+        id -> Uuid,
+        lat -> Float8,
+        lon -> Float8,
+        number -> Text,
+        street -> Text,
+        city -> Text,
+        region -> Text,
+        postcode -> Text,
+        country -> Text,
+        house_number -> Nullable<Int4>,
+        letter -> Nullable<Text>,
+        addition -> Nullable<Text>,
+        source -> Text,
+    }
+}
+
+table! {
+    region_counts (source, country, region) {
+        source -> Text,
+        country -> Text,
+        region -> Text,
+        count -> Int8,
     }
 }
 
@@ -17,10 +68,19 @@ table! {
         hash -> Text,
         version -> Text,
         processed_at -> Timestamp,
+        source -> Text,
+        inserted_count -> Int8,
+        updated_count -> Int8,
+        removed_count -> Int8,
     }
 }
 
+joinable!(address_changes -> states (state_id));
+
 allow_tables_to_appear_in_same_query!(
+    address_changes,
     addresses,
+    addresses_staging,
+    region_counts,
     states,
 );
@@ -0,0 +1,35 @@
+use futures::channel::mpsc;
+
+/// Commands sent to the `StateRefresher` by the admin API
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RefreshCommand {
+    /// Refreshes the data now, even when paused
+    Refresh { force: bool },
+    /// Skips the scheduled refreshes until resumed
+    Pause,
+    Resume,
+}
+
+/// Sends commands to a running `StateRefresher`.
+/// Commands received during a refresh are handled once it is over.
+#[derive(Debug, Clone)]
+pub struct RefreshControl {
+    sender: mpsc::UnboundedSender<RefreshCommand>
+}
+
+/// The `StateRefresher` isn't running, or has stopped
+#[derive(Debug)]
+pub struct RefresherStopped;
+
+impl RefreshControl {
+    pub fn new() -> (RefreshControl, mpsc::UnboundedReceiver<RefreshCommand>) {
+        let (sender, receiver) = mpsc::unbounded();
+        (RefreshControl { sender }, receiver)
+    }
+
+    pub fn send(&self, command: RefreshCommand) -> Result<(), RefresherStopped> {
+        self.sender
+            .unbounded_send(command)
+            .map_err(|_| RefresherStopped)
+    }
+}
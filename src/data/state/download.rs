@@ -0,0 +1,160 @@
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use log::{info, warn};
+use reqwest::header::{CONTENT_RANGE, RANGE};
+use reqwest::StatusCode;
+use tempfile::NamedTempFile;
+
+use crate::data::state::error::RefreshError;
+use crate::data::state::retry::RetryPolicy;
+use crate::data::state::shutdown::Shutdown;
+use crate::utils::ExistsExtension;
+
+/// File name prefix of the downloads, to find the ones left behind by an
+/// interrupted import
+pub const DOWNLOAD_PREFIX: &str = "postcode-data-";
+
+/// Downloads the given url to a temporary file in `download_dir`, chunk by
+/// chunk so that memory use doesn't depend on the size of the file.
+/// The file is removed once dropped.
+///
+/// Failed requests are retried according to `retry`. An interrupted download
+/// is resumed with a range request when the server supports it. The download
+/// is abandoned when a shutdown is requested.
+///
+/// The MD5 digest of the file is computed while downloading, and
+/// compared to `expected_hash` (as advertised in state.txt).
+pub async fn download_to_file(
+    client: &reqwest::Client,
+    url: &str,
+    expected_hash: &str,
+    download_dir: &Path,
+    retry: &RetryPolicy,
+    shutdown: &Shutdown
+) -> Result<NamedTempFile, RefreshError> {
+    let file = tempfile::Builder::new()
+        .prefix(DOWNLOAD_PREFIX)
+        .suffix(".zip")
+        .tempfile_in(download_dir)?;
+    let mut download = PartialDownload::new(file);
+
+    let mut attempt = 1;
+    loop {
+        match shutdown.interruptible(download.resume(client, url)).await {
+            Ok(()) => break,
+            Err(err) if retry.should_retry(attempt, &err) => {
+                shutdown.interruptible(async {
+                    retry.backoff(attempt, "Download", &err).await;
+                    Ok(())
+                })
+                .await?;
+                attempt += 1;
+            },
+            Err(err) => return Err(err),
+        }
+    }
+    let (file, digest, size) = download.finish()?;
+
+    info!("Downloaded {} MB to {}", size / 1_000_000, file.path().display());
+
+    let actual_hash = format!("{:x}", digest.compute());
+    verify_hash(expected_hash, &actual_hash)?;
+
+    Ok(file)
+}
+
+/// Data received so far, along with its digest
+struct PartialDownload {
+    writer: BufWriter<NamedTempFile>,
+    digest: md5::Context,
+    size: u64
+}
+
+impl PartialDownload {
+    fn new(file: NamedTempFile) -> Self {
+        PartialDownload {
+            writer: BufWriter::new(file),
+            digest: md5::Context::new(),
+            size: 0
+        }
+    }
+
+    /// Requests the data after what was already received, and appends it.
+    /// Starts over when the server ignores the range, but keeps the data
+    /// received so far when the request fails.
+    async fn resume(&mut self, client: &reqwest::Client, url: &str) -> Result<(), RefreshError> {
+        let mut request = client.get(url);
+        if self.size > 0 {
+            info!("Resuming download at {} MB", self.size / 1_000_000);
+            request = request.header(RANGE, format!("bytes={}-", self.size));
+        }
+        let mut response = request.send().await?;
+
+        if self.size > 0 && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            warn!("Range not satisfiable, restarting download");
+            self.restart()?;
+            response = client.get(url).send().await?;
+        }
+        let mut response = response.error_for_status()?;
+        if self.size > 0 && !self.is_continuation(&response) {
+            warn!("Range request not supported, restarting download");
+            self.restart()?;
+        }
+
+        while let Some(chunk) = response.chunk().await? {
+            self.writer.write_all(&chunk)?;
+            self.digest.consume(&chunk);
+            self.size += chunk.len() as u64;
+        }
+
+        Ok(())
+    }
+
+    /// Whether the response contains the data starting at the current size
+    fn is_continuation(&self, response: &reqwest::Response) -> bool {
+        let expected_range = format!("bytes {}-", self.size);
+        response.status() == StatusCode::PARTIAL_CONTENT && response
+            .headers()
+            .get(CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .exists(|range| range.starts_with(&expected_range))
+    }
+
+    fn restart(&mut self) -> Result<(), RefreshError> {
+        self.writer.flush()?;
+        let file = self.writer.get_mut();
+        file.as_file_mut().set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        self.digest = md5::Context::new();
+        self.size = 0;
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(NamedTempFile, md5::Context, u64), RefreshError> {
+        let file = self.writer
+            .into_inner()
+            .map_err(|err| RefreshError::IO(Box::new(err.into_error())))?;
+
+        Ok((file, self.digest, self.size))
+    }
+}
+
+pub fn verify_hash(expected: &str, actual: &str) -> Result<(), RefreshError> {
+    let expected = expected.trim();
+    if expected.is_empty() {
+        warn!("No hash available, the data file can't be verified");
+        return Ok(());
+    }
+
+    if !expected.eq_ignore_ascii_case(actual) {
+        return Err(RefreshError::HashMismatch {
+            expected: expected.to_owned(),
+            actual: actual.to_owned()
+        });
+    }
+
+    info!("Verified hash {}", actual);
+    Ok(())
+}
@@ -3,9 +3,39 @@ use std::fmt::Formatter;
 #[derive(Debug)]
 pub enum RefreshError {
     IO(Box<dyn std::fmt::Debug + Send>),
+    Http(Box<reqwest::Error>),
     InvalidZip(Box<zip::result::ZipError>),
     InvalidData(Box<dyn std::fmt::Debug + Send>),
     FileNotFound,
+    /// The digest of the downloaded file doesn't match the one from state.txt
+    HashMismatch { expected: String, actual: String },
+    /// The state info of a source couldn't be fetched, its current state is kept
+    StateInfoUnavailable,
+    /// Aborted because of a shutdown, nothing was published
+    Interrupted,
+    /// Another instance or process is running an import
+    ImportInProgress,
+}
+
+impl RefreshError {
+    /// Whether the same request may succeed when retried: network errors,
+    /// timeouts, server errors and rate limiting.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            RefreshError::Http(inner) => {
+                if inner.is_builder() || inner.is_redirect() {
+                    return false;
+                }
+                match inner.status() {
+                    Some(status) => status.is_server_error()
+                        || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                        || status == reqwest::StatusCode::REQUEST_TIMEOUT,
+                    None => true,
+                }
+            },
+            _ => false,
+        }
+    }
 }
 
 impl std::fmt::Display for RefreshError {
@@ -14,6 +44,9 @@ impl std::fmt::Display for RefreshError {
             RefreshError::IO(inner) => {
                 format!("IO: {:?}", inner)
             },
+            RefreshError::Http(inner) => {
+                format!("HTTP: {}", inner)
+            },
             RefreshError::InvalidZip(inner) => {
                 format!("Invalid zip data file: {}", inner)
             },
@@ -22,6 +55,18 @@ impl std::fmt::Display for RefreshError {
             },
             RefreshError::FileNotFound => {
                 "Could not find data file".into()
+            },
+            RefreshError::HashMismatch { expected, actual } => {
+                format!("Hash mismatch, expected {} but downloaded file has {}", expected, actual)
+            },
+            RefreshError::StateInfoUnavailable => {
+                "State info unavailable".into()
+            },
+            RefreshError::Interrupted => {
+                "Interrupted by shutdown".into()
+            },
+            RefreshError::ImportInProgress => {
+                "Another import is in progress".into()
             }
         };
         write!(f, "Refresh error: {}", msg)
@@ -48,9 +93,33 @@ impl <E> From<actix_web::error::BlockingError<E>> for RefreshError
     }
 }
 
+impl From<std::io::Error> for RefreshError {
+    fn from(error: std::io::Error) -> Self {
+        RefreshError::IO(Box::new(error))
+    }
+}
+
+impl From<r2d2::Error> for RefreshError {
+    fn from(error: r2d2::Error) -> Self {
+        RefreshError::IO(Box::new(error))
+    }
+}
+
+impl From<postgres::Error> for RefreshError {
+    fn from(error: postgres::Error) -> Self {
+        RefreshError::IO(Box::new(error))
+    }
+}
+
+impl From<openssl::error::ErrorStack> for RefreshError {
+    fn from(error: openssl::error::ErrorStack) -> Self {
+        RefreshError::IO(Box::new(error))
+    }
+}
+
 impl From<reqwest::Error> for RefreshError {
     fn from(error: reqwest::Error) -> Self {
-        RefreshError::IO(Box::new(error))
+        RefreshError::Http(Box::new(error))
     }
 }
 
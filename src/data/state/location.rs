@@ -0,0 +1,90 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+
+use tempfile::NamedTempFile;
+
+use crate::data::state::remote::RemoteConfig;
+
+/// Where the address data is fetched from, configured with `data.location`.
+///
+/// When it is not set, the data is downloaded from OpenAddresses (or the
+/// mirror configured in `RemoteConfig`). It can
+/// otherwise be the path of a local directory mirroring OpenAddresses
+/// (with a state.txt file), a zip file or a bare CSV file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DataLocation {
+    Remote(RemoteConfig),
+    Mirror(PathBuf),
+    File(PathBuf),
+}
+
+impl DataLocation {
+    pub fn new(path: Option<&PathBuf>, remote: &RemoteConfig) -> Self {
+        match path {
+            Some(path) if path.is_dir() => DataLocation::Mirror(path.clone()),
+            Some(path) => DataLocation::File(path.clone()),
+            None => DataLocation::Remote(remote.clone()),
+        }
+    }
+}
+
+/// A data file ready to be imported
+pub enum DataFile {
+    /// Downloaded, removed once dropped
+    Temporary(NamedTempFile),
+    Local(PathBuf),
+}
+
+impl DataFile {
+    pub fn open(&self) -> io::Result<File> {
+        match self {
+            DataFile::Temporary(file) => file.reopen(),
+            DataFile::Local(path) => File::open(path),
+        }
+    }
+}
+
+/// Path of a data file from state.txt in a mirror directory. Mirrors either
+/// keep the host and path of the original url (like `wget --mirror`), only
+/// its path, or only the file name. Paths that would leave the directory
+/// (absolute, or with `..`) are never resolved.
+pub fn resolve_mirror_path(directory: &Path, url: &str) -> Option<PathBuf> {
+    let without_scheme = url.find("://").map(|i| &url[i + 3..]);
+    let url_path = without_scheme.map(|rest| rest.find('/').map_or("", |i| &rest[i + 1..]));
+    let file_name = url.rsplit('/').next().unwrap_or_default();
+
+    let candidates = [
+        without_scheme.unwrap_or(url),
+        url_path.unwrap_or(url),
+        file_name,
+    ];
+    candidates
+        .iter()
+        .filter(|candidate| !candidate.is_empty() && stays_inside(Path::new(candidate)))
+        .map(|candidate| directory.join(candidate))
+        .find(|path| path.is_file())
+}
+
+fn stays_inside(path: &Path) -> bool {
+    path.components().all(|component| match component {
+        Component::Normal(_) | Component::CurDir => true,
+        Component::ParentDir | Component::RootDir | Component::Prefix(_) => false,
+    })
+}
+
+/// MD5 digest of a local file, in the same format as the hashes of state.txt
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut digest = md5::Context::new();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        digest.consume(&buffer[..read]);
+    }
+
+    Ok(format!("{:x}", digest.compute()))
+}
@@ -1,58 +1,171 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Instant;
+
+use actix_web::error::BlockingError;
 use actix_web::web;
-use diesel::PgConnection;
+use diesel::{Connection, PgConnection};
 use indicatif::ProgressBar;
-use log::{error, info};
-use regex::Regex;
+use log::{error, info, warn};
 use zip::ZipArchive;
 
 use crate::data::models::AddressRecord;
 use crate::data::models::State;
-use crate::data::repo::addresses::create_or_update_addresses;
-use crate::data::repo::states::{create_new_state, current_state};
+use crate::data::repo::addresses::{
+    clear_staged_addresses,
+    count_staged_addresses,
+    count_staged_addresses_by_source,
+    ImportCounts,
+    publish_staged_addresses,
+    update_region_counts
+};
+use crate::data::repo::bulk_load::BulkLoader;
+use crate::data::repo::changes::record_address_changes;
+use crate::data::repo::import_lock::ImportLock;
+use crate::data::repo::states::{create_new_state, current_state, set_state_counts};
+use crate::data::state::download::{DOWNLOAD_PREFIX, download_to_file, verify_hash};
 use crate::data::state::error::RefreshError;
+use crate::data::state::location::{DataFile, DataLocation, hash_file, resolve_mirror_path};
+use crate::data::state::monitor::{RefreshMonitor, RefreshPhase};
+use crate::data::state::remote::RemoteConfig;
+use crate::data::state::retry::with_retries;
+use crate::data::state::shutdown::Shutdown;
+use crate::data::state::source::Source;
 use crate::db::Pool;
+use crate::metrics::{IMPORT_RECORDS_EXPECTED, IMPORT_RECORDS_READ, IMPORTED_ADDRESSES, REFRESH_DURATION};
 use crate::utils::ExistsExtension;
 
+pub mod control;
+pub mod download;
 pub mod error;
+pub mod location;
+pub mod monitor;
+pub mod remote;
+pub mod retry;
+pub mod shutdown;
+pub mod source;
 pub mod state_refresher;
 
 #[derive(Debug)]
 pub struct StateInfo {
+    pub source: String,
     pub url: String,
     pub hash: String,
     pub version: String,
     pub address_count: usize
 }
 
+/// What to refresh, and where from
+#[derive(Debug, Clone)]
+pub struct RefreshConfig {
+    pub sources: Vec<Source>,
+    pub location: DataLocation,
+    /// Imports are bulk loaded over their own connection, outside of the pool
+    pub database_url: String,
+    /// Skips the verification of the server certificate by the bulk loader
+    pub database_insecure_tls: bool,
+    /// Import the data even when its version is the current one
+    pub force: bool
+}
+
 #[derive(Debug)]
 pub struct DataStatus {
+    pub source: Source,
     pub state_info: Option<StateInfo>,
     pub current_state: Option<State>
 }
 
-const BATCH_SIZE: usize = 2500;
-const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/79.0.3945.117 Safari/537.36";
-const STATE_INFO_URL: &str = "http://results.openaddresses.io/state.txt";
+/// Minimum share of the advertised address count a data file must contain
+/// to be published, anything less is considered truncated.
+const MIN_COMPLETENESS: f64 = 0.95;
+const STATE_INFO_FILE: &str = "state.txt";
+const ZIP_SIGNATURE: [u8; 4] = [b'P', b'K', 3, 4];
 
-pub async fn refresh_state(pool: &Pool) -> Result<(), RefreshError> {
-    let status = get_data_status(&pool).await?;
+/// Refreshes the configured sources, until the shutdown is requested.
+/// Its progress and outcome are reported to the monitor.
+pub async fn refresh_state(
+    pool: &Pool,
+    config: &RefreshConfig,
+    monitor: &RefreshMonitor,
+    shutdown: &Shutdown
+) -> Result<(), RefreshError> {
+    monitor.started(config.force);
+    let timer = Instant::now();
+    let result = refresh_sources(pool, config, monitor, shutdown).await;
+    let outcome = if result.is_ok() { "success" } else { "failure" };
+    REFRESH_DURATION
+        .with_label_values(&[outcome])
+        .observe(timer.elapsed().as_secs_f64());
+    monitor.finished(&result);
+
+    result
+}
+
+async fn refresh_sources(
+    pool: &Pool,
+    config: &RefreshConfig,
+    monitor: &RefreshMonitor,
+    shutdown: &Shutdown
+) -> Result<(), RefreshError> {
+    let statuses = shutdown
+        .interruptible(get_data_status(&pool, &config.sources, &config.location))
+        .await?;
+    let mut result = Ok(());
+    // Sources are refreshed independently, a failure for one of them
+    // shouldn't prevent the others from being updated.
+    for status in statuses {
+        if shutdown.is_requested() {
+            return Err(RefreshError::Interrupted);
+        }
+        let source_id = status.source.id.clone();
+        if let Err(err) = refresh_source(&pool, config, status, monitor, shutdown).await {
+            match err {
+                RefreshError::Interrupted => {
+                    info!("Refresh of source {} interrupted by shutdown", source_id);
+                    return Err(err);
+                },
+                RefreshError::ImportInProgress => {
+                    warn!("Source {} not refreshed, another import is in progress", source_id);
+                },
+                _ => { error!("Error while refreshing source: {}", err); },
+            }
+            result = Err(err);
+        }
+    }
+
+    result
+}
+
+async fn refresh_source(
+    pool: &Pool,
+    config: &RefreshConfig,
+    status: DataStatus,
+    monitor: &RefreshMonitor,
+    shutdown: &Shutdown
+) -> Result<(), RefreshError> {
+    let source = status.source;
     match status.state_info {
         Some(state_info) => {
-            let up_to_date = status
+            let up_to_date = !config.force && status
                 .current_state
                 .exists(|s| s.version == state_info.version);
 
             if up_to_date {
-                info!("Data already up to date (state: {})", state_info.version);
+                info!(
+                    "Data already up to date (source: {}, state: {})",
+                    source.id,
+                    state_info.version
+                );
             } else {
-                info!("Updating data...");
-                match update_state(&pool, state_info).await {
-                    Ok(_) => { info!("Successfully updated data"); },
+                info!("Updating data for source {}...", source.id);
+                match update_state(&pool, config, &source, state_info, monitor, shutdown).await {
+                    Ok(_) => { info!("Successfully updated data for source {}", source.id); },
                     Err(err) => {
                         if status.current_state.is_none() {
-                            panic!(
-                                "Couldn't update data, and no fallback is available:\n{}",
-                                err
+                            error!(
+                                "Couldn't update data for source {}, and no fallback is available",
+                                source.id
                             );
                         }
                         return Err(err);
@@ -62,30 +175,39 @@ pub async fn refresh_state(pool: &Pool) -> Result<(), RefreshError> {
         },
         None => {
             if status.current_state.is_none() {
-                panic!("Couldn't fetch data info, and no fallback is available");
+                error!(
+                    "Couldn't fetch data info for source {}, and no fallback is available",
+                    source.id
+                );
             } else {
-                info!("Falling back to current state");
+                info!("Falling back to current state for source {}", source.id);
             }
+            return Err(RefreshError::StateInfoUnavailable);
         }
     }
 
     Ok(())
 }
 
-fn get_state_info<R: std::io::Read>(reader: R) -> Result<Option<StateInfo>, RefreshError> {
+fn get_state_info<R: std::io::Read>(
+    reader: R,
+    source: &Source
+) -> Result<Option<StateInfo>, RefreshError> {
     let mut reader = csv::ReaderBuilder::new()
         .delimiter(b'\t')
         .from_reader(reader);
 
+    let state_name = source.state_name();
     for record in reader.records() {
         let r = record?;
-        if r.as_slice().starts_with("nl/countrywide.json") {
+        if &r[0] == state_name {
             let address_count = r[4]
                 .parse::<usize>()
                 .map_err(|err| RefreshError::InvalidData(Box::new(err)))?;
 
             return Ok(Some(StateInfo {
                 address_count,
+                source: source.id.clone(),
                 url: r[8].to_owned(),
                 hash: r[10].to_owned(),
                 version: r[15].to_owned()
@@ -96,120 +218,427 @@ fn get_state_info<R: std::io::Read>(reader: R) -> Result<Option<StateInfo>, Refr
     Ok(None)
 }
 
-pub async fn get_data_status(pool: &Pool) -> Result<DataStatus, RefreshError> {
-    info!("Fetching state info at {}", STATE_INFO_URL);
-    let response = reqwest::get(STATE_INFO_URL).await;
-    let conn = pool.get().unwrap();
-    let current_state = web::block(move || current_state(&conn)).await?;
+pub async fn get_data_status(
+    pool: &Pool,
+    sources: &[Source],
+    location: &DataLocation
+) -> Result<Vec<DataStatus>, RefreshError> {
+    let state_infos = fetch_state_infos(sources, location).await?;
 
-    match response {
-        Ok(resp) => {
-            match resp.bytes().await {
-                Ok(bytes) => {
-                    let state_info = web::block(move || {
-                        let cursor = std::io::Cursor::new(&bytes);
-                        get_state_info(cursor)
-                    })
-                    .await?;
-                    Ok(DataStatus { state_info, current_state })
-                }
+    let mut statuses = Vec::with_capacity(sources.len());
+    for (source, state_info) in sources.iter().zip(state_infos) {
+        let conn = pool.get()?;
+        let state_source = source.id.clone();
+        let current_state = web::block(move || current_state(&conn, &state_source)).await?;
+
+        statuses.push(DataStatus {
+            source: source.clone(),
+            state_info,
+            current_state
+        });
+    }
+
+    Ok(statuses)
+}
+
+/// Reports and discards what an interrupted import left behind: addresses
+/// staged but never published, and partial downloads. As no new state was
+/// created, the next refresh imports the same version again.
+///
+/// Skipped while another import is in progress, as what it staged and
+/// downloaded so far would be discarded as well.
+pub async fn recover_interrupted_imports(
+    pool: &Pool,
+    config: &RefreshConfig
+) -> Result<(), RefreshError> {
+    let lock = match lock_import(pool).await {
+        Ok(lock) => lock,
+        Err(RefreshError::ImportInProgress) => {
+            info!("Another import is in progress, not recovering interrupted imports");
+            return Ok(());
+        },
+        Err(err) => return Err(err),
+    };
+    let (lock, staged_counts) = web::block(move || -> Result<_, diesel::result::Error> {
+        let counts = count_staged_addresses_by_source(lock.conn())?;
+        for staged in &counts {
+            clear_staged_addresses(lock.conn(), &staged.source)?;
+        }
+        Ok((lock, counts))
+    })
+    .await?;
+    for staged in staged_counts {
+        warn!(
+            "Discarded {} staged addresses of an interrupted import of source {}",
+            staged.count,
+            staged.source
+        );
+    }
+
+    if let DataLocation::Remote(remote) = &config.location {
+        for entry in std::fs::read_dir(remote.download_dir())? {
+            let path = entry?.path();
+            let partial_download = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .exists(|name| name.starts_with(DOWNLOAD_PREFIX));
+            if partial_download && path.is_file() {
+                warn!("Removing download of an interrupted import: {}", path.display());
+                std::fs::remove_file(&path)?;
+            }
+        }
+    }
+    drop(lock);
+
+    Ok(())
+}
+
+/// State info of each source, `None` when it isn't available
+async fn fetch_state_infos(
+    sources: &[Source],
+    location: &DataLocation
+) -> Result<Vec<Option<StateInfo>>, RefreshError> {
+    let state_info_bytes = match location {
+        DataLocation::Remote(config) => fetch_remote_state_info(config).await,
+        DataLocation::Mirror(directory) => {
+            let path = directory.join(STATE_INFO_FILE);
+            info!("Reading state info at {}", path.display());
+            match web::block(move || std::fs::read(path)).await {
+                Ok(bytes) => Some(bytes::Bytes::from(bytes)),
                 Err(err) => {
-                    error!("Error getting bytes from response: {}", err);
-                    Ok(DataStatus { state_info: None, current_state })
-                }
+                    error!("Error reading state info: {}", err);
+                    None
+                },
             }
         },
+        DataLocation::File(path) => {
+            let path = path.clone();
+            let block_sources = sources.to_vec();
+            let state_infos = web::block(move || local_file_state_infos(&path, &block_sources))
+                .await
+                .unwrap_or_else(|err| {
+                    error!("Error reading data file: {}", err);
+                    sources.iter().map(|_| None).collect()
+                });
+            return Ok(state_infos);
+        },
+    };
+
+    let mut state_infos = Vec::with_capacity(sources.len());
+    for source in sources {
+        let state_info = match &state_info_bytes {
+            Some(bytes) => {
+                let bytes = bytes.clone();
+                let info_source = source.clone();
+                web::block(move || {
+                    let cursor = std::io::Cursor::new(&bytes);
+                    get_state_info(cursor, &info_source)
+                })
+                .await?
+            },
+            None => None,
+        };
+        if state_info_bytes.is_some() && state_info.is_none() {
+            error!("Source {} not found in state info", source.id);
+        }
+        state_infos.push(state_info);
+    }
+
+    Ok(state_infos)
+}
+
+async fn fetch_remote_state_info(config: &RemoteConfig) -> Option<bytes::Bytes> {
+    info!("Fetching state info at {}", config.state_info_url);
+    let client = match config.client() {
+        Ok(client) => client,
+        Err(err) => {
+            error!("Error creating HTTP client: {}", err);
+            return None;
+        },
+    };
+    let response = with_retries(&config.retry, "State info request", || async {
+        let bytes = client
+            .get(&config.state_info_url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        Ok(bytes)
+    })
+    .await;
+    match response {
+        Ok(bytes) => Some(bytes),
         Err(err) => {
             error!("Error fetching state info: {}", err);
-            Ok(DataStatus { state_info: None, current_state })
+            None
         },
     }
 }
 
+/// A local data file has no state.txt, its digest is used as version so
+/// that it is only imported again when its content changes.
+/// A zip file can contain the data of several sources, while a bare csv
+/// file can only be used for a single source.
+fn local_file_state_infos(
+    path: &Path,
+    sources: &[Source]
+) -> Result<Vec<Option<StateInfo>>, RefreshError> {
+    info!("Reading state info from {}", path.display());
+    let zip = is_zip(&mut File::open(path)?)?;
+    if !zip && sources.len() > 1 {
+        error!("A csv data file can only be used with a single source");
+        return Ok(sources.iter().map(|_| None).collect());
+    }
+    let hash = hash_file(path)?;
+
+    let state_infos = sources
+        .iter()
+        .map(|source| Some(StateInfo {
+            source: source.id.clone(),
+            url: path.display().to_string(),
+            hash: hash.clone(),
+            version: hash.clone(),
+            address_count: 0
+        }))
+        .collect();
+
+    Ok(state_infos)
+}
+
 pub async fn update_state(
     pool: &Pool,
-    state_info: StateInfo
+    config: &RefreshConfig,
+    source: &Source,
+    state_info: StateInfo,
+    monitor: &RefreshMonitor,
+    shutdown: &Shutdown
 ) -> Result<(), RefreshError> {
-    info!("Downloading state version {} from {}", state_info.version, state_info.url);
-    let req = reqwest::Client::builder()
-        .user_agent(USER_AGENT)
-        .build()?
-        .get(&state_info.url);
-
-    let resp_bytes = req
-        .send()
-        .await?
-        .bytes()
-        .await?;
+    let lock = lock_import(pool).await?;
+    let data_file = match &config.location {
+        DataLocation::Remote(remote) => {
+            monitor.phase(&source.id, RefreshPhase::Downloading);
+            let url = remote.data_url(&state_info.url);
+            info!("Downloading state version {} from {}", state_info.version, url);
+            let client = remote.client()?;
+            let file = download_to_file(
+                &client,
+                &url,
+                &state_info.hash,
+                &remote.download_dir(),
+                &remote.retry,
+                shutdown
+            )
+            .await?;
+
+            DataFile::Temporary(file)
+        },
+        DataLocation::Mirror(directory) => {
+            let path = resolve_mirror_path(directory, &state_info.url)
+                .ok_or(RefreshError::FileNotFound)?;
+            info!("Reading state version {} from {}", state_info.version, path.display());
 
-    info!("Downloaded zip, size: {} MB", resp_bytes.len() / 1_000_000);
+            let expected_hash = state_info.hash.clone();
+            let hashed_path = path.clone();
+            web::block(move || -> Result<(), RefreshError> {
+                verify_hash(&expected_hash, &hash_file(&hashed_path)?)
+            })
+            .await
+            .map_err(blocking_refresh_error)?;
+            DataFile::Local(path)
+        },
+        DataLocation::File(path) => {
+            info!("Reading {}", path.display());
+            DataFile::Local(path.clone())
+        },
+    };
     info!("Searching for csv file");
 
-    let conn = pool.get().unwrap();
+    let source = source.clone();
+    let database_url = config.database_url.clone();
+    let insecure_tls = config.database_insecure_tls;
+    let monitor = monitor.clone();
+    let shutdown = shutdown.clone();
     web::block(move || {
+        let mut loader = BulkLoader::connect(&database_url, insecure_tls)?;
         process_data_response(
+            &source,
             state_info,
-            &resp_bytes,
-            &conn
+            data_file.open()?,
+            lock.conn(),
+            &mut loader,
+            &monitor,
+            &shutdown
         )
     })
-    .await?;
+    .await
+    .map_err(blocking_refresh_error)?;
 
     Ok(())
 }
 
-fn process_data_response(
+/// Only one import runs at a time, see `ImportLock`
+async fn lock_import(pool: &Pool) -> Result<ImportLock, RefreshError> {
+    let conn = pool.get()?;
+    web::block(move || ImportLock::try_acquire(conn))
+        .await?
+        .ok_or(RefreshError::ImportInProgress)
+}
+
+/// Keeps interruptions and rejected data distinguishable from other errors
+fn blocking_refresh_error(error: BlockingError<RefreshError>) -> RefreshError {
+    match error {
+        BlockingError::Error(RefreshError::Interrupted) => RefreshError::Interrupted,
+        BlockingError::Error(err @ RefreshError::HashMismatch { .. }) => err,
+        BlockingError::Error(err @ RefreshError::InvalidData(_)) => err,
+        err => RefreshError::from(err),
+    }
+}
+
+fn process_data_response<R: Read + Seek>(
+    source: &Source,
     state_info: StateInfo,
-    bytes: &bytes::Bytes,
-    conn: &PgConnection
+    mut reader: R,
+    conn: &PgConnection,
+    loader: &mut BulkLoader,
+    monitor: &RefreshMonitor,
+    shutdown: &Shutdown
 ) -> Result<(), RefreshError> {
-    let reader = std::io::Cursor::new(&bytes);
-    let mut zip = ZipArchive::new(reader)?;
-    let re = Regex::new(r"nl.*\.csv").expect("Could not create regex");
+    if !is_zip(&mut reader)? {
+        info!("Data file is a csv file");
+        return import_data_file(source, state_info, reader, conn, loader, monitor, shutdown);
+    }
 
-    let mut found = false;
+    let mut zip = ZipArchive::new(BufReader::new(reader))?;
     for i in 0..zip.len() {
         let file = zip.by_index(i)?;
         info!("File: {}", file.name());
-        if re.is_match(file.name()) {
+        if source.matches_data_file(file.name()) {
             info!("Found csv file");
-            info!("Updating database records...");
-            let mut reader = csv::Reader::from_reader(file);
-
-            let mut batch = Vec::<AddressRecord>::with_capacity(BATCH_SIZE);
-            let progress_bar = ProgressBar::new(state_info.address_count as u64);
-            for record in reader.deserialize() {
-                let address_record: AddressRecord = record?;
-                batch.push(address_record);
-                if batch.len() == BATCH_SIZE {
-                    process_batch(&conn, &mut batch, &progress_bar)?;
-                }
-            };
-            process_batch(&conn, &mut batch, &progress_bar)?;
-            progress_bar.finish();
-
-            create_new_state(&conn, &state_info)?;
-            info!("Done");
-            found = true;
-            break;
+            return import_data_file(source, state_info, file, conn, loader, monitor, shutdown);
         }
     }
-    if !found {
-        return Err(RefreshError::FileNotFound)
+
+    Err(RefreshError::FileNotFound)
+}
+
+fn import_data_file<R: Read>(
+    source: &Source,
+    state_info: StateInfo,
+    file: R,
+    conn: &PgConnection,
+    loader: &mut BulkLoader,
+    monitor: &RefreshMonitor,
+    shutdown: &Shutdown
+) -> Result<(), RefreshError> {
+    info!("Loading records in staging table...");
+    let progress_bar = ProgressBar::new(state_info.address_count as u64);
+    let records_read = IMPORT_RECORDS_READ.with_label_values(&[&source.id]);
+    records_read.set(0);
+    IMPORT_RECORDS_EXPECTED
+        .with_label_values(&[&source.id])
+        .set(state_info.address_count as i64);
+    monitor.phase(&source.id, RefreshPhase::Loading);
+    let mut reader = csv::Reader::from_reader(file);
+    // Aborting the copy rolls it back, leaving the staging table empty
+    let records = reader
+        .deserialize::<AddressRecord>()
+        .map(|record| if shutdown.is_requested() {
+            Err(RefreshError::Interrupted)
+        } else {
+            record.map_err(RefreshError::from)
+        })
+        .inspect(|_| {
+            progress_bar.inc(1);
+            records_read.inc();
+        });
+    let record_count = loader.load(&source.id, &source.country(), records)?;
+    progress_bar.finish();
+
+    if shutdown.is_requested() {
+        clear_staged_addresses(conn, &source.id)?;
+        return Err(RefreshError::Interrupted);
     }
 
+    if let Err(err) = validate_staged_data(conn, &state_info, record_count) {
+        clear_staged_addresses(conn, &source.id)?;
+        return Err(err);
+    }
+
+    info!("Publishing staged records...");
+    monitor.phase(&source.id, RefreshPhase::Publishing);
+    let publish_start = Instant::now();
+    let counts = publish_state(conn, &state_info)?;
+    info!(
+        "Published {} new, {} updated and {} removed addresses in {:.1}s",
+        counts.inserted,
+        counts.updated,
+        counts.removed,
+        publish_start.elapsed().as_secs_f64()
+    );
+    let imported = |change: &str, count: i64| IMPORTED_ADDRESSES
+        .with_label_values(&[&source.id, change])
+        .inc_by(count);
+    imported("inserted", counts.inserted);
+    imported("updated", counts.updated);
+    imported("removed", counts.removed);
+    clear_staged_addresses(conn, &source.id)?;
+    info!("Done");
+
     Ok(())
 }
 
-fn process_batch(
+/// Whether the file starts with the signature of a zip archive.
+/// The reader is rewound afterwards.
+fn is_zip<R: Read + Seek>(reader: &mut R) -> Result<bool, RefreshError> {
+    let mut signature = [0; 4];
+    let read = reader.read(&mut signature)?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    Ok(read == signature.len() && signature == ZIP_SIGNATURE)
+}
+
+/// Checks that the staged data is complete before it gets published
+fn validate_staged_data(
     conn: &PgConnection,
-    batch: &mut Vec<AddressRecord>,
-    progress_bar: &ProgressBar
-) -> Result<(), diesel::result::Error> {
-    create_or_update_addresses(&conn, &batch)?;
-    progress_bar.inc(batch.len() as u64);
-    batch.clear();
+    state_info: &StateInfo,
+    record_count: usize
+) -> Result<(), RefreshError> {
+    let min_record_count = (state_info.address_count as f64 * MIN_COMPLETENESS) as usize;
+    if record_count < min_record_count {
+        return Err(RefreshError::InvalidData(Box::new(format!(
+            "Data file has {} records, expected {}",
+            record_count,
+            state_info.address_count
+        ))));
+    }
+
+    if count_staged_addresses(conn, &state_info.source)? == 0 {
+        return Err(RefreshError::InvalidData(Box::new("Data file has no addresses")));
+    }
 
     Ok(())
 }
 
+/// Publishes the staged addresses as a new state of their source, recording
+/// the changes against the previous state.
+pub fn publish_state(
+    conn: &PgConnection,
+    state_info: &StateInfo
+) -> Result<ImportCounts, diesel::result::Error> {
+    conn.transaction(|| {
+        // Changes are only relevant against a previous version,
+        // not for the first import of a source.
+        let previous_state = current_state(conn, &state_info.source)?;
+        let state = create_new_state(conn, state_info)?;
+        if previous_state.is_some() {
+            let change_count = record_address_changes(conn, &state_info.source, state.id)?;
+            info!("Recorded {} address changes", change_count);
+        }
+        let counts = publish_staged_addresses(conn, &state_info.source)?;
+        set_state_counts(conn, state.id, &counts)?;
+        update_region_counts(conn, &state_info.source)?;
+        Ok(counts)
+    })
+}
+
@@ -0,0 +1,140 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{NaiveDateTime, Utc};
+use serde::Serialize;
+
+use crate::data::state::error::RefreshError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RefreshResult {
+    Success,
+    Failure,
+}
+
+/// Step of the refresh of a source
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RefreshPhase {
+    Downloading,
+    /// Loading the records in the staging table
+    Loading,
+    Publishing,
+}
+
+/// Refresh in progress. The number of records loaded so far is
+/// tracked by the `import_records_read` metric.
+#[derive(Debug, Clone, Serialize)]
+pub struct CurrentRefresh {
+    pub started_at: NaiveDateTime,
+    pub force: bool,
+    /// Source being refreshed, once its data is being imported
+    pub source: Option<String>,
+    pub phase: Option<RefreshPhase>
+}
+
+/// What the `StateRefresher` did last, and when it runs next
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RefreshReport {
+    pub refreshing: bool,
+    /// Scheduled refreshes are skipped while paused
+    pub paused: bool,
+    pub last_attempt_at: Option<NaiveDateTime>,
+    pub last_result: Option<RefreshResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    pub last_success_at: Option<NaiveDateTime>,
+    pub next_refresh_at: Option<NaiveDateTime>,
+    /// A refresh was requested through the admin API, and hasn't started yet
+    #[serde(skip)]
+    pub refresh_requested: bool
+}
+
+/// Shared between the `StateRefresher`, which reports its refreshes,
+/// and the API, which exposes them
+#[derive(Debug, Clone, Default)]
+pub struct RefreshMonitor {
+    report: Arc<Mutex<RefreshReport>>,
+    current: Arc<Mutex<Option<CurrentRefresh>>>
+}
+
+impl RefreshMonitor {
+    pub fn report(&self) -> RefreshReport {
+        self.report.lock().unwrap().clone()
+    }
+
+    pub fn current(&self) -> Option<CurrentRefresh> {
+        self.current.lock().unwrap().clone()
+    }
+
+    pub fn scheduled(&self, delay: Duration) {
+        let next_refresh_at = chrono::Duration::from_std(delay)
+            .ok()
+            .and_then(|delay| Utc::now().naive_utc().checked_add_signed(delay));
+        let mut report = self.report.lock().unwrap();
+        report.paused = false;
+        report.next_refresh_at = next_refresh_at;
+    }
+
+    pub fn paused(&self) {
+        let mut report = self.report.lock().unwrap();
+        report.paused = true;
+        report.next_refresh_at = None;
+    }
+
+    /// Records a requested refresh, unless one is already requested or in progress.
+    /// Returns whether the refresh should be requested.
+    pub fn request_refresh(&self) -> bool {
+        let mut report = self.report.lock().unwrap();
+        if report.refreshing || report.refresh_requested {
+            return false;
+        }
+        report.refresh_requested = true;
+        true
+    }
+
+    /// The requested refresh won't run, as the refresher is stopped
+    pub fn refresh_request_failed(&self) {
+        self.report.lock().unwrap().refresh_requested = false;
+    }
+
+    pub fn started(&self, force: bool) {
+        let started_at = Utc::now().naive_utc();
+        let mut report = self.report.lock().unwrap();
+        report.refreshing = true;
+        report.refresh_requested = false;
+        report.last_attempt_at = Some(started_at);
+        report.next_refresh_at = None;
+        *self.current.lock().unwrap() = Some(CurrentRefresh {
+            started_at,
+            force,
+            source: None,
+            phase: None
+        });
+    }
+
+    pub fn phase(&self, source: &str, phase: RefreshPhase) {
+        if let Some(current) = self.current.lock().unwrap().as_mut() {
+            current.source = Some(source.to_owned());
+            current.phase = Some(phase);
+        }
+    }
+
+    pub fn finished(&self, result: &Result<(), RefreshError>) {
+        *self.current.lock().unwrap() = None;
+        let mut report = self.report.lock().unwrap();
+        report.refreshing = false;
+        match result {
+            Ok(_) => {
+                report.last_result = Some(RefreshResult::Success);
+                report.last_error = None;
+                report.last_success_at = report.last_attempt_at;
+            },
+            Err(err) => {
+                report.last_result = Some(RefreshResult::Failure);
+                report.last_error = Some(err.to_string());
+            },
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::config::duration_secs;
+use crate::data::state::error::RefreshError;
+use crate::data::state::retry::RetryPolicy;
+
+const DEFAULT_STATE_INFO_URL: &str = "http://results.openaddresses.io/state.txt";
+const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/79.0.3945.117 Safari/537.36";
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 30;
+// Downloads of the largest sources can take a while
+const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 3600;
+
+/// How to reach OpenAddresses, or a mirror of it, over HTTP.
+/// The `[data.remote]` section of the config.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RemoteConfig {
+    /// Url of state.txt, overridden by `STATE_INFO_URL`
+    pub state_info_url: String,
+    /// Replaces the scheme, host and port of the data file urls found in
+    /// state.txt, overridden by `DATA_DOWNLOAD_BASE_URL`
+    pub download_base_url: Option<String>,
+    /// Where downloads are written, overridden by `DATA_DOWNLOAD_DIR`.
+    /// Defaults to the system temporary directory.
+    pub download_dir: Option<PathBuf>,
+    /// Overridden by `DATA_HTTP_PROXY`. The `HTTP_PROXY` and `HTTPS_PROXY`
+    /// environment variables are used otherwise.
+    pub proxy: Option<String>,
+    /// PEM file of additional root certificates, overridden by `DATA_CA_BUNDLE`
+    pub ca_bundle: Option<PathBuf>,
+    /// Overridden by `DATA_CONNECT_TIMEOUT_SECS`
+    #[serde(rename = "connect_timeout_secs", deserialize_with = "duration_secs")]
+    pub connect_timeout: Duration,
+    /// Timeout of whole requests, downloads included,
+    /// overridden by `DATA_REQUEST_TIMEOUT_SECS`
+    #[serde(rename = "request_timeout_secs", deserialize_with = "duration_secs")]
+    pub request_timeout: Duration,
+    /// Overridden by `DATA_USER_AGENT`
+    pub user_agent: String,
+    /// Retries of the state info and data file requests
+    pub retry: RetryPolicy
+}
+
+impl Default for RemoteConfig {
+    fn default() -> Self {
+        RemoteConfig {
+            state_info_url: DEFAULT_STATE_INFO_URL.to_owned(),
+            download_base_url: None,
+            download_dir: None,
+            proxy: None,
+            ca_bundle: None,
+            connect_timeout: Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS),
+            request_timeout: Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECS),
+            user_agent: DEFAULT_USER_AGENT.to_owned(),
+            retry: RetryPolicy::default()
+        }
+    }
+}
+
+impl RemoteConfig {
+    pub fn client(&self) -> Result<reqwest::Client, RefreshError> {
+        let mut builder = reqwest::Client::builder()
+            .user_agent(self.user_agent.as_str())
+            .connect_timeout(self.connect_timeout)
+            .timeout(self.request_timeout);
+
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy.as_str())?);
+        }
+        if let Some(ca_bundle) = &self.ca_bundle {
+            let pem = std::fs::read_to_string(ca_bundle)?;
+            for certificate in split_pem_certificates(&pem) {
+                builder = builder.add_root_certificate(reqwest::Certificate::from_pem(certificate.as_bytes())?);
+            }
+        }
+
+        Ok(builder.build()?)
+    }
+
+    pub fn download_dir(&self) -> PathBuf {
+        self.download_dir
+            .clone()
+            .unwrap_or_else(env::temp_dir)
+    }
+
+    /// Url to download a data file from
+    pub fn data_url(&self, url: &str) -> String {
+        let base_url = match &self.download_base_url {
+            Some(base_url) => base_url.trim_end_matches('/'),
+            None => return url.to_owned(),
+        };
+        let path = url
+            .find("://")
+            .map(|i| &url[i + 3..])
+            .and_then(|rest| rest.find('/').map(|i| &rest[i..]))
+            .unwrap_or(url);
+
+        format!("{}/{}", base_url, path.trim_start_matches('/'))
+    }
+}
+
+/// A bundle contains several certificates, which have to be added one by one
+fn split_pem_certificates(pem: &str) -> Vec<String> {
+    const END: &str = "-----END CERTIFICATE-----";
+
+    pem
+        .split_terminator(END)
+        .filter(|certificate| certificate.contains("-----BEGIN CERTIFICATE-----"))
+        .map(|certificate| format!("{}{}\n", certificate.trim_start(), END))
+        .collect()
+}
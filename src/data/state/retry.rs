@@ -0,0 +1,97 @@
+use std::future::Future;
+use std::time::Duration;
+
+use log::warn;
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::config::duration_secs;
+use crate::data::state::error::RefreshError;
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const DEFAULT_INITIAL_DELAY_SECS: u64 = 2;
+const DEFAULT_MAX_DELAY_SECS: u64 = 60;
+
+/// Retries of failed requests, with an exponential backoff.
+/// The `[data.remote.retry]` section of the config.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Attempts including the first one, overridden by `DATA_RETRY_ATTEMPTS`
+    pub max_attempts: u32,
+    /// Overridden by `DATA_RETRY_INITIAL_DELAY_SECS`
+    #[serde(rename = "initial_delay_secs", deserialize_with = "duration_secs")]
+    pub initial_delay: Duration,
+    /// Overridden by `DATA_RETRY_MAX_DELAY_SECS`
+    #[serde(rename = "max_delay_secs", deserialize_with = "duration_secs")]
+    pub max_delay: Duration
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            initial_delay: Duration::from_secs(DEFAULT_INITIAL_DELAY_SECS),
+            max_delay: Duration::from_secs(DEFAULT_MAX_DELAY_SECS)
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before the attempt following the given one (starting at 1).
+    /// It doubles after each attempt, and half of it is random so that
+    /// instances failing at the same time don't retry at the same time.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(16);
+        let delay = self.initial_delay
+            .checked_mul(1 << exponent)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+
+        let half = delay / 2;
+        half + half.mul_f64(rand::thread_rng().gen::<f64>())
+    }
+
+    /// Whether the failed attempt should be followed by another one
+    pub fn should_retry(&self, attempt: u32, error: &RefreshError) -> bool {
+        attempt < self.max_attempts && error.is_transient()
+    }
+
+    /// Waits before the attempt following the failed one
+    pub async fn backoff(&self, attempt: u32, description: &str, error: &RefreshError) {
+        let delay = self.delay(attempt);
+        warn!(
+            "{} failed (attempt {}/{}), retrying in {:.1}s: {}",
+            description,
+            attempt,
+            self.max_attempts,
+            delay.as_secs_f64(),
+            error
+        );
+        actix_rt::time::delay_for(delay).await;
+    }
+}
+
+/// Runs the operation until it succeeds, a permanent error occurs,
+/// or the maximum number of attempts is reached.
+pub async fn with_retries<T, F, R>(
+    policy: &RetryPolicy,
+    description: &str,
+    mut operation: F
+) -> Result<T, RefreshError>
+    where
+        F: FnMut() -> R,
+        R: Future<Output = Result<T, RefreshError>>,
+{
+    let mut attempt = 1;
+    loop {
+        match operation().await {
+            Ok(result) => return Ok(result),
+            Err(err) if policy.should_retry(attempt, &err) => {
+                policy.backoff(attempt, description, &err).await;
+                attempt += 1;
+            },
+            Err(err) => return Err(err),
+        }
+    }
+}
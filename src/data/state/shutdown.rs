@@ -0,0 +1,64 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use futures::channel::oneshot;
+use futures::future::{self, Either, FutureExt, Shared};
+use futures::Future;
+use futures::pin_mut;
+
+use crate::data::state::error::RefreshError;
+
+/// Asks the data refresh to stop, shared between the task that handles the
+/// shutdown and the refresh.
+///
+/// Imports check it between records, and are aborted before anything gets
+/// published. An import that is already being published is left to finish,
+/// as it is a single transaction.
+#[derive(Clone)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+    sender: Arc<Mutex<Option<oneshot::Sender<()>>>>,
+    receiver: Shared<oneshot::Receiver<()>>
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        let (sender, receiver) = oneshot::channel();
+        Shutdown {
+            requested: Arc::new(AtomicBool::new(false)),
+            sender: Arc::new(Mutex::new(Some(sender))),
+            receiver: receiver.shared()
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+        if let Some(sender) = self.sender.lock().unwrap().take() {
+            let _ = sender.send(());
+        }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once the shutdown is requested
+    pub async fn requested(&self) {
+        let _ = self.receiver.clone().await;
+    }
+
+    /// Runs the future, unless the shutdown is requested first
+    pub async fn interruptible<T, F>(&self, future: F) -> Result<T, RefreshError>
+        where
+            F: Future<Output = Result<T, RefreshError>>,
+    {
+        let requested = self.requested();
+        pin_mut!(future, requested);
+        match future::select(future, requested).await {
+            Either::Left((result, _)) => result,
+            Either::Right(_) => Err(RefreshError::Interrupted),
+        }
+    }
+}
@@ -0,0 +1,32 @@
+/// An OpenAddresses source, identified by its path in state.txt
+/// without the extension (e.g. `nl/countrywide`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+    pub id: String
+}
+
+impl Source {
+    pub fn new(id: &str) -> Self {
+        Self { id: id.trim().trim_end_matches(".json").to_lowercase() }
+    }
+
+    /// ISO 3166-1 alpha-2 code of the country this source covers,
+    /// taken from the first segment of the source id.
+    pub fn country(&self) -> String {
+        self.id
+            .split('/')
+            .next()
+            .unwrap_or_default()
+            .to_uppercase()
+    }
+
+    /// Name of the source in the "source" column of state.txt
+    pub fn state_name(&self) -> String {
+        format!("{}.json", self.id)
+    }
+
+    /// Whether the given file from the data zip is this source's CSV
+    pub fn matches_data_file(&self, file_name: &str) -> bool {
+        file_name.ends_with(&format!("{}.csv", self.id))
+    }
+}
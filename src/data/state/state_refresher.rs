@@ -1,32 +1,137 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use futures::{future, FutureExt, pin_mut, select, StreamExt};
+use futures::channel::mpsc::UnboundedReceiver;
 use log::{error, info};
 
-use crate::data::state::refresh_state;
+use crate::config::Config;
+use crate::data::state::control::{RefreshCommand, RefreshControl};
+use crate::data::state::monitor::RefreshMonitor;
+use crate::data::state::{recover_interrupted_imports, refresh_state, RefreshConfig};
+use crate::data::state::shutdown::Shutdown;
 use crate::db::Pool;
 
 pub struct StateRefresher {
     pub interval: Duration,
+    /// Shorter interval used after a failed refresh
+    pub retry_interval: Duration,
     // If the first tick should be immediate
-    pub immediate: bool
+    pub immediate: bool,
+    pub refresh: RefreshConfig,
+    pub monitor: RefreshMonitor,
+    commands: Option<UnboundedReceiver<RefreshCommand>>
+}
+
+enum Event {
+    Scheduled,
+    Command(RefreshCommand),
+    /// Every `RefreshControl` was dropped
+    ControlClosed,
+    Shutdown,
 }
 
 impl StateRefresher {
-    pub fn new(interval: Duration, immediate: bool) -> Self {
-        Self { interval, immediate }
+    pub fn new(config: &Config, immediate: bool, monitor: RefreshMonitor) -> Self {
+        Self {
+            interval: config.data.refresh_interval,
+            retry_interval: config.data.refresh_retry_interval,
+            immediate,
+            refresh: config.refresh_config(),
+            monitor,
+            commands: None
+        }
     }
 
-    pub async fn start(self, pool: &Pool) {
-        let mut interval = actix_rt::time::interval(self.interval);
-        if !self.immediate {
-            interval.tick().await;
+    /// Control of the refresher once started, for the admin API
+    pub fn control(&mut self) -> RefreshControl {
+        let (control, commands) = RefreshControl::new();
+        self.commands = Some(commands);
+        control
+    }
+
+    /// Refreshes the data until the shutdown is requested, the current
+    /// import is then aborted (see `Shutdown`)
+    pub async fn start(mut self, pool: &Pool, shutdown: &Shutdown) {
+        if let Err(err) = recover_interrupted_imports(pool, &self.refresh).await {
+            error!("Error while recovering interrupted imports: {}", err);
         }
+
+        let first_refresh = if self.immediate { Duration::from_secs(0) } else { self.interval };
+        let mut next_refresh_at = Instant::now() + first_refresh;
+        let mut paused = false;
         loop {
-            interval.tick().await;
+            if paused {
+                self.monitor.paused();
+            } else {
+                self.monitor.scheduled(next_refresh_at.saturating_duration_since(Instant::now()));
+            }
+            let force = match self.next_event(paused, next_refresh_at, shutdown).await {
+                Event::Scheduled => false,
+                Event::Command(RefreshCommand::Refresh { force }) => {
+                    info!("StateRefresher: refresh requested");
+                    force
+                },
+                Event::Command(RefreshCommand::Pause) => {
+                    info!("StateRefresher: paused");
+                    paused = true;
+                    continue;
+                },
+                Event::Command(RefreshCommand::Resume) => {
+                    info!("StateRefresher: resumed");
+                    paused = false;
+                    continue;
+                },
+                Event::ControlClosed => {
+                    self.commands = None;
+                    continue;
+                },
+                Event::Shutdown => break,
+            };
+
             info!("StateRefresher: refreshing data...");
-            if let Err(err) = refresh_state(&pool).await {
-                error!("Error while refreshing state: {}", err);
+            let refresh = RefreshConfig { force, ..self.refresh.clone() };
+            let result = refresh_state(&pool, &refresh, &self.monitor, shutdown).await;
+            if shutdown.is_requested() {
+                break;
             }
+            let next_refresh = match result {
+                Ok(_) => self.interval,
+                Err(err) => {
+                    error!("Error while refreshing state: {}", err);
+                    info!(
+                        "StateRefresher: retrying in {} minutes",
+                        self.retry_interval.as_secs() / 60
+                    );
+                    self.retry_interval
+                },
+            };
+            next_refresh_at = Instant::now() + next_refresh;
+        }
+        info!("StateRefresher: stopped");
+    }
+
+    async fn next_event(&mut self, paused: bool, next_refresh_at: Instant, shutdown: &Shutdown) -> Event {
+        let scheduled = async {
+            if paused {
+                future::pending::<()>().await
+            } else {
+                actix_rt::time::delay_for(next_refresh_at.saturating_duration_since(Instant::now())).await
+            }
+        }.fuse();
+        let commands = &mut self.commands;
+        let command = async {
+            match commands {
+                Some(commands) => commands.next().await,
+                None => future::pending().await,
+            }
+        }.fuse();
+        let requested = shutdown.requested().fuse();
+        pin_mut!(scheduled, command, requested);
+
+        select! {
+            _ = scheduled => Event::Scheduled,
+            command = command => command.map_or(Event::ControlClosed, Event::Command),
+            _ = requested => Event::Shutdown,
         }
     }
 }
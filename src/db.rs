@@ -5,36 +5,29 @@ use diesel::r2d2::ConnectionManager;
 
 use dotenv::dotenv;
 
+use crate::config::DatabaseConfig;
+
 pub type Pool = r2d2::Pool<ConnectionManager<PgConnection>>;
+pub type PooledConnection = r2d2::PooledConnection<ConnectionManager<PgConnection>>;
 
-const DEFAULT_DB_POOL_SIZE: usize = 15;
+pub fn init_connection_pool(config: &DatabaseConfig) -> Result<Pool, r2d2::Error> {
+    let manager = ConnectionManager::<PgConnection>::new(config.url.as_str());
+    r2d2::Pool::builder()
+        .max_size(config.pool_size)
+        .build(manager)
+}
 
-pub fn init_connection_pool() -> Pool {
+#[allow(dead_code)] // Only used in tests
+pub fn test_database_url() -> String {
     dotenv().ok();
 
-    let database_url = env::var("DATABASE_URL")
-        .expect("DATABASE_URL must be set");
-
-    let pool_size = env::var("DATABASE_POOL_SIZE")
-        .map(|size| size
-            .parse::<u32>()
-            .expect("DATABASE_POOL_SIZE must be an integer")
-        )
-        .unwrap_or(DEFAULT_DB_POOL_SIZE as u32);
-
-    let manager = ConnectionManager::<PgConnection>::new(database_url);
-    r2d2::Pool::builder()
-        .max_size(pool_size)
-        .build(manager)
-        .expect("Failed to create db pool")
+    env::var("TEST_DATABASE_URL")
+        .expect("TEST_DATABASE_URL must be set")
 }
 
 #[allow(dead_code)] // Used inside a lazy_static macro
 pub fn init_test_connection_pool() -> Pool {
-    dotenv().ok();
-
-    let database_url = env::var("TEST_DATABASE_URL")
-        .expect("TEST_DATABASE_URL must be set");
+    let database_url = test_database_url();
 
     let manager = ConnectionManager::<PgConnection>::new(database_url);
     r2d2::Pool::builder()
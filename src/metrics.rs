@@ -0,0 +1,71 @@
+use lazy_static::lazy_static;
+use prometheus::{HistogramVec, IntCounterVec, IntGauge, IntGaugeVec};
+
+// Refreshes download and import millions of addresses, they take minutes
+const REFRESH_DURATION_BUCKETS: &[f64] = &[1.0, 5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1200.0, 1800.0, 3600.0];
+
+lazy_static! {
+    pub static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "http_requests_total",
+        "Number of HTTP requests",
+        &["route", "method", "status"]
+    ).unwrap();
+
+    pub static ref HTTP_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "http_request_duration_seconds",
+        "Duration of HTTP requests",
+        &["route", "method", "status"]
+    ).unwrap();
+
+    pub static ref ADDRESS_QUERY_DURATION: HistogramVec = register_histogram_vec!(
+        "address_query_duration_seconds",
+        "Duration of address lookup queries",
+        &["query"]
+    ).unwrap();
+
+    pub static ref DB_POOL_CONNECTIONS: IntGauge = register_int_gauge!(
+        "db_pool_connections",
+        "Number of connections of the database pool"
+    ).unwrap();
+
+    pub static ref DB_POOL_IDLE_CONNECTIONS: IntGauge = register_int_gauge!(
+        "db_pool_idle_connections",
+        "Number of idle connections of the database pool"
+    ).unwrap();
+
+    pub static ref DB_POOL_MAX_CONNECTIONS: IntGauge = register_int_gauge!(
+        "db_pool_max_connections",
+        "Maximum number of connections of the database pool"
+    ).unwrap();
+
+    pub static ref IMPORT_RECORDS_READ: IntGaugeVec = register_int_gauge_vec!(
+        "import_records_read",
+        "Number of records read by the current or last import of a source",
+        &["source"]
+    ).unwrap();
+
+    pub static ref IMPORT_RECORDS_EXPECTED: IntGaugeVec = register_int_gauge_vec!(
+        "import_records_expected",
+        "Number of records advertised for the current or last import of a source",
+        &["source"]
+    ).unwrap();
+
+    pub static ref IMPORTED_ADDRESSES: IntCounterVec = register_int_counter_vec!(
+        "imported_addresses_total",
+        "Number of addresses inserted, updated or removed by imports",
+        &["source", "change"]
+    ).unwrap();
+
+    pub static ref REFRESH_DURATION: HistogramVec = register_histogram_vec!(
+        "refresh_duration_seconds",
+        "Duration of data refreshes",
+        &["result"],
+        REFRESH_DURATION_BUCKETS.to_vec()
+    ).unwrap();
+
+    pub static ref STATE_AGE: IntGaugeVec = register_int_gauge_vec!(
+        "state_age_seconds",
+        "Time since the current state of a source was imported",
+        &["source"]
+    ).unwrap();
+}
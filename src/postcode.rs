@@ -0,0 +1,109 @@
+use std::fmt::Formatter;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// Validation and normalization rules for the postcodes of a country.
+pub struct PostcodeRule {
+    /// ISO 3166-1 alpha-2 country code
+    pub country: &'static str,
+    /// Format of the postcode once upper-cased and stripped of spaces,
+    /// which is also the form stored in the database
+    pattern: Regex
+}
+
+impl PostcodeRule {
+    fn new(country: &'static str, pattern: &str) -> Self {
+        Self {
+            country,
+            pattern: Regex::new(pattern).expect("Could not create postcode regex")
+        }
+    }
+
+    pub fn normalize(&self, postcode: &str) -> Result<String, PostcodeError> {
+        let compact: String = postcode
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .flat_map(char::to_uppercase)
+            .collect();
+
+        if compact.is_empty() {
+            return Err(PostcodeError::Empty);
+        }
+        if !self.pattern.is_match(&compact) {
+            return Err(PostcodeError::InvalidFormat(Some(self.country)));
+        }
+
+        Ok(compact)
+    }
+}
+
+lazy_static! {
+    static ref RULES: Vec<PostcodeRule> = vec![
+        // 1011 PN, stored as 1011PN. The letters SA, SD and SS are not used.
+        PostcodeRule::new("NL", r"^[1-9][0-9]{3}(?:[A-RT-Z][A-Z]|S[BCE-RT-Z])$"),
+        PostcodeRule::new("BE", r"^[1-9][0-9]{3}$"),
+        PostcodeRule::new("DE", r"^[0-9]{5}$"),
+        PostcodeRule::new("DK", r"^[1-9][0-9]{3}$"),
+    ];
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PostcodeError {
+    Empty,
+    // Country for which the postcode was validated, if any
+    InvalidFormat(Option<&'static str>),
+    UnsupportedCountry(String),
+}
+
+impl std::fmt::Display for PostcodeError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            PostcodeError::Empty => {
+                write!(f, "Postcode is empty")
+            },
+            PostcodeError::InvalidFormat(Some(country)) => {
+                write!(f, "Postcode is not a valid {} postcode", country)
+            },
+            PostcodeError::InvalidFormat(None) => {
+                write!(f, "Postcode is not valid for any supported country")
+            },
+            PostcodeError::UnsupportedCountry(country) => {
+                write!(f, "Country {} is not supported", country)
+            },
+        }
+    }
+}
+
+pub fn rule_for(country: &str) -> Option<&'static PostcodeRule> {
+    RULES.iter().find(|rule| rule.country.eq_ignore_ascii_case(country))
+}
+
+/// Validates the postcode and returns its canonical form.
+/// When no country is given, the postcode must be valid for at least one
+/// of the supported countries.
+pub fn normalize_postcode(
+    country: Option<&str>,
+    postcode: &str
+) -> Result<String, PostcodeError> {
+    match country {
+        Some(code) => {
+            rule_for(code)
+                .ok_or_else(|| PostcodeError::UnsupportedCountry(code.to_uppercase()))?
+                .normalize(postcode)
+        },
+        None => {
+            let mut error = PostcodeError::Empty;
+            for rule in RULES.iter() {
+                match rule.normalize(postcode) {
+                    Ok(normalized) => return Ok(normalized),
+                    Err(err) => error = err,
+                }
+            }
+            match error {
+                PostcodeError::InvalidFormat(_) => Err(PostcodeError::InvalidFormat(None)),
+                err => Err(err),
+            }
+        },
+    }
+}
@@ -0,0 +1,218 @@
+use serde::Serialize;
+
+use crate::data::house_number::parse_house_number;
+use crate::data::models::Address;
+
+// Relative weights of the fields in the confidence score
+const STREET_WEIGHT: f64 = 0.3;
+const NUMBER_WEIGHT: f64 = 0.25;
+const POSTCODE_WEIGHT: f64 = 0.3;
+const CITY_WEIGHT: f64 = 0.15;
+
+/// Below this confidence, the best candidate is not considered a match
+const MIN_CONFIDENCE: f64 = 0.6;
+/// Candidates closer than this to the best one make the result ambiguous
+const AMBIGUITY_MARGIN: f64 = 0.03;
+
+/// Address as submitted for verification, every field may contain typos
+#[derive(Debug)]
+pub struct SubmittedAddress {
+    pub street: Option<String>,
+    pub number: String,
+    pub postcode: Option<String>,
+    pub city: Option<String>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    /// The submitted address matches a known address
+    Exact,
+    /// The submitted address is close to a single known address
+    Corrected,
+    /// The submitted address is close to several known addresses
+    Ambiguous,
+    NotFound,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FieldDifference {
+    pub field: &'static str,
+    pub submitted: String,
+    pub canonical: String
+}
+
+#[derive(Debug, Serialize)]
+pub struct Candidate {
+    pub confidence: f64,
+    pub address: Address
+}
+
+#[derive(Debug, Serialize)]
+pub struct Verification {
+    pub verdict: Verdict,
+    pub confidence: f64,
+    pub differences: Vec<FieldDifference>,
+    /// Canonical record to replace the submitted address with
+    pub address: Option<Address>,
+    /// Closest candidates, when the verdict is ambiguous
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub candidates: Vec<Candidate>
+}
+
+/// Scores every candidate against the submitted address and picks the best one
+pub fn verify(submitted: &SubmittedAddress, candidates: Vec<Address>) -> Verification {
+    let mut scored = candidates
+        .into_iter()
+        .map(|address| Candidate { confidence: confidence(submitted, &address), address })
+        .collect::<Vec<Candidate>>();
+    scored.sort_by(|a, b| b.confidence.partial_cmp(&a.confidence).unwrap_or(std::cmp::Ordering::Equal));
+
+    let best_confidence = scored.first().map_or(0.0, |c| c.confidence);
+    if best_confidence < MIN_CONFIDENCE {
+        return Verification {
+            verdict: Verdict::NotFound,
+            confidence: best_confidence,
+            differences: vec![],
+            address: None,
+            candidates: vec![]
+        };
+    }
+
+    let close_count = scored
+        .iter()
+        .take_while(|c| best_confidence - c.confidence < AMBIGUITY_MARGIN)
+        .count();
+    if close_count > 1 {
+        scored.truncate(close_count);
+        return Verification {
+            verdict: Verdict::Ambiguous,
+            confidence: best_confidence,
+            differences: vec![],
+            address: None,
+            candidates: scored
+        };
+    }
+
+    let best = scored.remove(0);
+    let differences = differences(submitted, &best.address);
+    Verification {
+        verdict: if differences.is_empty() { Verdict::Exact } else { Verdict::Corrected },
+        confidence: best.confidence,
+        differences,
+        address: Some(best.address),
+        candidates: vec![]
+    }
+}
+
+/// Weighted similarity of the submitted fields, between 0 and 1.
+/// Fields that weren't submitted don't count.
+fn confidence(submitted: &SubmittedAddress, address: &Address) -> f64 {
+    let scores = [
+        (STREET_WEIGHT, submitted.street.as_ref().map(|s| similarity(s, &address.street))),
+        (NUMBER_WEIGHT, Some(number_similarity(&submitted.number, address))),
+        (POSTCODE_WEIGHT, submitted.postcode.as_ref().map(|p| similarity(&compact(p), &address.postcode))),
+        (CITY_WEIGHT, submitted.city.as_ref().map(|c| similarity(c, &address.city))),
+    ];
+
+    let (total, weights) = scores
+        .iter()
+        .filter_map(|(weight, score)| score.map(|s| (s * weight, *weight)))
+        .fold((0.0, 0.0), |(total, weights), (score, weight)| (total + score, weights + weight));
+
+    total / weights
+}
+
+/// Fields that weren't submitted aren't differences, the canonical
+/// address only completes them.
+fn differences(submitted: &SubmittedAddress, address: &Address) -> Vec<FieldDifference> {
+    let fields = [
+        ("street", submitted.street.as_ref(), &address.street),
+        ("number", Some(&submitted.number), &address.number),
+        ("postcode", submitted.postcode.as_ref(), &address.postcode),
+        ("city", submitted.city.as_ref(), &address.city),
+    ];
+
+    fields
+        .iter()
+        .filter_map(|(field, value, canonical)| value.map(|v| (*field, v, *canonical)))
+        .filter(|(field, value, canonical)| match *field {
+            "number" => number_similarity(value, address) < 1.0,
+            "postcode" => compact(value) != compact(canonical),
+            _ => normalize(value) != normalize(canonical),
+        })
+        .map(|(field, value, canonical)| FieldDifference {
+            field,
+            submitted: value.clone(),
+            canonical: canonical.clone()
+        })
+        .collect()
+}
+
+/// The number itself must be equal, the letter and addition only
+/// increase the similarity.
+fn number_similarity(submitted: &str, address: &Address) -> f64 {
+    match (parse_house_number(submitted), address.house_number) {
+        (Some(parsed), Some(number)) => {
+            if parsed.number != number {
+                return 0.0;
+            }
+            let letter = eq_ignore_case(&parsed.letter, &address.letter);
+            let addition = eq_ignore_case(&parsed.addition, &address.addition);
+            0.6 + if letter { 0.2 } else { 0.0 } + if addition { 0.2 } else { 0.0 }
+        },
+        _ => similarity(submitted, &address.number),
+    }
+}
+
+fn eq_ignore_case(a: &Option<String>, b: &Option<String>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Lowercase alphanumeric words, separated by single spaces
+fn normalize(value: &str) -> String {
+    value
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Postcodes are compared without spaces
+fn compact(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Normalized Levenshtein similarity, between 0 and 1
+fn similarity(a: &str, b: &str) -> f64 {
+    let a = normalize(a).chars().collect::<Vec<char>>();
+    let b = normalize(b).chars().collect::<Vec<char>>();
+    let max_len = a.len().max(b.len());
+    if max_len == 0 {
+        return 1.0;
+    }
+
+    let mut previous = (0..=b.len()).collect::<Vec<usize>>();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == cb { 0 } else { 1 };
+            current[j + 1] = substitution
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    1.0 - previous[b.len()] as f64 / max_len as f64
+}